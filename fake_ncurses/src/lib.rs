@@ -1,10 +1,38 @@
-use ncurses::{attr_t, NCURSES_ATTR_T, WINDOW};
+pub use ncurses::attr_t;
+use ncurses::{NCURSES_ATTR_T, WINDOW};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+thread_local! {
+    /* Keys put back with ungetch, for getch to read like typed ones */
+    static KEYS: RefCell<VecDeque<i32>> = const { RefCell::new(VecDeque::new()) };
+}
 
 #[allow(non_snake_case)]
 pub const fn A_BOLD() -> attr_t {
     0
 }
 
+#[allow(non_snake_case)]
+pub const fn A_DIM() -> attr_t {
+    0
+}
+
+#[allow(non_snake_case)]
+pub const fn A_NORMAL() -> attr_t {
+    0
+}
+
+#[allow(non_snake_case)]
+pub const fn A_REVERSE() -> attr_t {
+    0
+}
+
+#[allow(non_snake_case)]
+pub const fn A_UNDERLINE() -> attr_t {
+    0
+}
+
 #[allow(non_snake_case)]
 pub fn LINES() -> i32 {
     10
@@ -23,6 +51,7 @@ pub fn COLOR_PAIR(_n: i16) -> attr_t {
 pub const COLOR_BLACK: i16 = 0;
 pub const COLOR_RED: i16 = 1;
 pub const COLOR_GREEN: i16 = 2;
+pub const COLOR_MAGENTA: i16 = 5;
 pub const COLOR_CYAN: i16 = 6;
 pub const COLOR_WHITE: i16 = 7;
 
@@ -46,6 +75,10 @@ pub fn mvaddch(_y: i32, _x: i32, _c: chtype) -> i32 {
     0
 }
 
+pub fn has_colors() -> bool {
+    true
+}
+
 pub fn start_color() -> i32 {
     0
 }
@@ -90,6 +123,12 @@ pub fn endwin() -> i32 {
 }
 
 pub fn getch() -> i32 {
+    KEYS.with(|keys| keys.borrow_mut().pop_front())
+        .unwrap_or(ERR)
+}
+
+pub fn ungetch(ch: i32) -> i32 {
+    KEYS.with(|keys| keys.borrow_mut().push_front(ch));
     0
 }
 
@@ -111,12 +150,24 @@ pub enum WchResult {
     KeyCode(i32),
 }
 
+pub const ERR: i32 = -1;
 pub const KEY_LEFT: i32 = 0x104;
 pub const KEY_RIGHT: i32 = 0x105;
 pub const KEY_DOWN: i32 = 0x102;
 pub const KEY_UP: i32 = 0x103;
+pub const KEY_HOME: i32 = 0x106;
 pub const KEY_BACKSPACE: i32 = 0x107;
 pub const KEY_DC: i32 = 0x14a;
 pub const KEY_NPAGE: i32 = 0x152;
 pub const KEY_PPAGE: i32 = 0x153;
+pub const KEY_END: i32 = 0x168;
+pub const KEY_SDC: i32 = 0x17f;
+pub const KEY_SLEFT: i32 = 0x189;
+pub const KEY_SRIGHT: i32 = 0x192;
+pub const KEY_ENTER: i32 = 0x157;
+pub const KEY_F1: i32 = 0x109;
+pub const KEY_F2: i32 = 0x10a;
+pub const KEY_F3: i32 = 0x10b;
+pub const KEY_F4: i32 = 0x10c;
+pub const KEY_F5: i32 = 0x10d;
 pub const KEY_RESIZE: i32 = 0x19a;
@@ -0,0 +1,191 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+
+/* A favorite can be a sequence of commands, stored on a single line
+ * of the favorites file with the commands separated by the delimiter:
+ *
+ * `cargo fmt ;;; cargo clippy ;;; cargo test`
+ */
+pub const SEQUENCE_DELIMITER: &str = ";;;";
+
+pub fn split(entry: &str) -> Vec<&str> {
+    entry
+        .split(SEQUENCE_DELIMITER)
+        .map(str::trim)
+        .filter(|cmd| !cmd.is_empty())
+        .collect()
+}
+
+pub fn is_sequence(entry: &str) -> bool {
+    split(entry).len() > 1
+}
+
+pub fn assemble(entry: &str, joiner: &str) -> String {
+    if is_sequence(entry) {
+        split(entry).join(joiner)
+    } else {
+        entry.to_string()
+    }
+}
+
+/* Favorites can be tagged, to narrow the favorites view down to a tag.
+ * Tags are kept in a file of their own, next to the favorites file, so
+ * that stays a plain list of commands. One `tag=command` per line:
+ *
+ * `deploy=kubectl apply -f prod.yml`
+ */
+pub type Tags = HashMap<String, Vec<String>>;
+
+pub fn parse_tags(lines: &[String]) -> Tags {
+    let mut tags = Tags::new();
+    lines
+        .iter()
+        .filter_map(|line| line.split_once('='))
+        .map(|(tag, command)| (tag.trim(), command.trim()))
+        .filter(|(tag, command)| is_tag(tag) && !command.is_empty())
+        .for_each(|(tag, command)| {
+            let commands = tags.entry(tag.to_string()).or_default();
+            if !commands.iter().any(|cmd| cmd == command) {
+                commands.push(command.to_string());
+            }
+        });
+    tags
+}
+
+pub fn tag_lines(tags: &Tags) -> Vec<String> {
+    names(tags)
+        .into_iter()
+        .flat_map(|tag| tags[tag].iter().map(move |cmd| format!("{}={}", tag, cmd)))
+        .collect()
+}
+
+pub fn names(tags: &Tags) -> Vec<&str> {
+    tags.keys().map(String::as_str).sorted().collect()
+}
+
+pub fn tags_of<'a>(tags: &'a Tags, command: &str) -> Vec<&'a str> {
+    names(tags)
+        .into_iter()
+        .filter(|tag| tags[*tag].iter().any(|cmd| cmd == command))
+        .collect()
+}
+
+pub fn is_tag(word: &str) -> bool {
+    !word.is_empty() && !word.contains('=') && !word.contains(char::is_whitespace)
+}
+
+/* A favorite can have a note on what it does, which the search looks
+ * at too. Notes are kept in a file of their own, like the tags. One
+ * `command<TAB>note` per line, split at the last tab:
+ *
+ * `kubectl apply -f prod.yml	deploys to production`
+ */
+pub type Notes = HashMap<String, String>;
+
+pub fn parse_notes(lines: &[String]) -> Notes {
+    lines
+        .iter()
+        .filter_map(|line| line.rsplit_once('\t'))
+        .map(|(command, note)| (command.trim(), note.trim()))
+        .filter(|(command, note)| !command.is_empty() && !note.is_empty())
+        .map(|(command, note)| (command.to_string(), note.to_string()))
+        .collect()
+}
+
+pub fn note_lines(notes: &Notes) -> Vec<String> {
+    notes
+        .iter()
+        .sorted()
+        .map(|(command, note)| format!("{}\t{}", command, note))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    #[rstest(
+        entry,
+        expected,
+        case("cargo test", vec!["cargo test"]),
+        case("cargo fmt ;;; cargo test", vec!["cargo fmt", "cargo test"]),
+        case("make;;;make install;;;", vec!["make", "make install"]),
+        case(" ;;; ls -la ;;;  ", vec!["ls -la"])
+    )]
+    fn split(entry: &str, expected: Vec<&str>) {
+        assert_eq!(super::split(entry), expected);
+    }
+
+    #[rstest(
+        entry,
+        expected,
+        case("cargo test", false),
+        case("cargo fmt ;;; cargo test", true),
+        case("ls -la ;;;", false),
+        case("case $x in a) echo a;; esac", false)
+    )]
+    fn is_sequence(entry: &str, expected: bool) {
+        assert_eq!(super::is_sequence(entry), expected);
+    }
+
+    #[rstest(
+        entry,
+        joiner,
+        expected,
+        case("cargo test", " && ", "cargo test"),
+        case("cargo fmt ;;; cargo test", " && ", "cargo fmt && cargo test"),
+        case("cd /tmp ;;; ls ;;; cd -", "\n", "cd /tmp\nls\ncd -"),
+        case("echo a ;;; ", " && ", "echo a ;;; ")
+    )]
+    fn assemble(entry: &str, joiner: &str, expected: &str) {
+        assert_eq!(super::assemble(entry, joiner), expected);
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|&line| line.into()).collect()
+    }
+
+    #[rstest(
+        file,
+        expected,
+        case(&["deploy=make deploy"], vec!["deploy=make deploy"]),
+        case(
+            &["db=psql", "deploy=make deploy", "db=pg_dump > dump.sql"],
+            vec!["db=psql", "db=pg_dump > dump.sql", "deploy=make deploy"]
+        ),
+        case(&["db = FOO=1 psql ", "db=FOO=1 psql"], vec!["db=FOO=1 psql"]),
+        case(&["make deploy", "=ls", "db=", "my tag=ls"], vec![])
+    )]
+    fn tags_roundtrip(file: &[&str], expected: Vec<&str>) {
+        let tags = super::parse_tags(&lines(file));
+        assert_eq!(super::tag_lines(&tags), expected);
+    }
+
+    #[rstest(
+        command,
+        expected,
+        case("psql", vec!["db", "work"]),
+        case("make deploy", vec!["deploy"]),
+        case("ls", vec![])
+    )]
+    fn tags_of(command: &str, expected: Vec<&str>) {
+        let tags = super::parse_tags(&lines(&["work=psql", "deploy=make deploy", "db=psql"]));
+        assert_eq!(super::tags_of(&tags, command), expected);
+    }
+
+    #[rstest(
+        file,
+        expected,
+        case(&["psql\tdatabase shell"], vec!["psql\tdatabase shell"]),
+        case(
+            &["make deploy\tto prod", "ls -la\tall files"],
+            vec!["ls -la\tall files", "make deploy\tto prod"]
+        ),
+        case(&["printf 'a\tb'\t tab in it "], vec!["printf 'a\tb'\ttab in it"]),
+        case(&["no note", "ls\t", "\tnothing to note"], vec![])
+    )]
+    fn notes_roundtrip(file: &[&str], expected: Vec<&str>) {
+        let notes = super::parse_notes(&lines(file));
+        assert_eq!(super::note_lines(&notes), expected);
+    }
+}
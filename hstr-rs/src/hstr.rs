@@ -1,28 +1,445 @@
-use crate::{io::read_from_home, state::Commands};
+use crate::io::read_from_home;
+use std::path::{Path, PathBuf};
 
-pub fn get_bash_history() -> (Vec<String>, Commands) {
-    let history = read_from_home(".bash_history").unwrap();
-    let commands = Commands::from_history("bash", &history);
-    (history, commands)
+pub trait HistorySource {
+    fn load(&self) -> Vec<String>;
+
+    fn load_timestamped(&self) -> Vec<(Option<u64>, String)> {
+        self.load().into_iter().map(|cmd| (None, cmd)).collect()
+    }
+
+    /* Timestamps and durations, which only zsh records */
+    fn load_timed(&self) -> Vec<(Option<u64>, Option<u64>, String)> {
+        self.load_timestamped()
+            .into_iter()
+            .map(|(ts, cmd)| (ts, None, cmd))
+            .collect()
+    }
 }
 
-pub fn get_zsh_history() -> (Vec<String>, Commands) {
-    let history = zsh::process_history()
-        .split('\n')
-        .map(|x| x.to_string())
-        .collect::<Vec<String>>();
-    let commands = Commands::from_history("zsh", &history);
-    (history, commands)
+/* Paths of the sources are relative to home directory, unless absolute */
+pub struct Bash {
+    pub path: PathBuf,
+}
+
+pub struct Zsh {
+    pub path: PathBuf,
+}
+
+pub struct Pwsh {
+    pub path: PathBuf,
+}
+
+impl HistorySource for Bash {
+    fn load(&self) -> Vec<String> {
+        self.load_timestamped()
+            .into_iter()
+            .map(|(_, cmd)| cmd)
+            .collect()
+    }
+
+    fn load_timestamped(&self) -> Vec<(Option<u64>, String)> {
+        bash::split_timestamps(read_from_home(&self.path).unwrap())
+            .into_iter()
+            .filter_map(|(ts, cmd)| tidy(cmd).map(|cmd| (ts, cmd)))
+            .collect()
+    }
+}
+
+impl HistorySource for Zsh {
+    fn load(&self) -> Vec<String> {
+        self.load_timestamped()
+            .into_iter()
+            .map(|(_, cmd)| cmd)
+            .collect()
+    }
+
+    fn load_timestamped(&self) -> Vec<(Option<u64>, String)> {
+        self.load_timed()
+            .into_iter()
+            .map(|(ts, _, cmd)| (ts, cmd))
+            .collect()
+    }
+
+    fn load_timed(&self) -> Vec<(Option<u64>, Option<u64>, String)> {
+        zsh::process_history(&self.path)
+            .into_iter()
+            .filter_map(|(metadata, cmd)| {
+                tidy(cmd).map(|cmd| {
+                    (
+                        metadata.map(|m| m.started),
+                        metadata.map(|m| m.duration),
+                        cmd,
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+impl HistorySource for Pwsh {
+    fn load(&self) -> Vec<String> {
+        pwsh::join_continuations(read_from_home(&self.path).unwrap())
+            .into_iter()
+            .filter_map(tidy)
+            .collect()
+    }
+}
+
+pub fn tidy(entry: String) -> Option<String> {
+    /* Trailing whitespace is never intentional and blank entries
+     * can't be run, but leading whitespace is kept, because bash
+     * uses it to tell which commands to leave out of the history
+     */
+    let trimmed = entry.trim_end();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_string())
+    }
+}
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "pwsh"];
+
+pub fn unsupported(shell: &str) -> Option<String> {
+    /* The shell is "N/A" when it couldn't be detected */
+    if SHELLS.contains(&shell) {
+        return None;
+    }
+    let problem = if shell == "N/A" {
+        String::from("Couldn't detect your shell")
+    } else {
+        format!("{} is not supported yet", shell)
+    };
+    Some(format!(
+        "{}. Supported shells are {}, pick one with --shell.",
+        problem,
+        SHELLS.join(", ")
+    ))
+}
+
+pub fn history_source(shell: &str, path: PathBuf) -> Option<Box<dyn HistorySource>> {
+    match shell {
+        "bash" => Some(Box::new(Bash { path })),
+        "zsh" => Some(Box::new(Zsh { path })),
+        "pwsh" => Some(Box::new(Pwsh { path })),
+        _ => None,
+    }
+}
+
+pub fn history_file(shell: &str) -> String {
+    match shell {
+        "pwsh" => pwsh::HISTORY.to_string(),
+        _ => format!(".{}_history", shell),
+    }
+}
+
+pub fn history_path(shell: &str, histfile: Option<String>) -> PathBuf {
+    /* Both bash and zsh can keep the history elsewhere with HISTFILE */
+    match histfile {
+        Some(histfile) if !histfile.is_empty() && shell != "pwsh" => PathBuf::from(histfile),
+        _ => PathBuf::from(history_file(shell)),
+    }
+}
+
+pub fn get_directory_history(shell: &str) -> Option<Vec<(String, String)>> {
+    /* Shells don't record where a command was run, so the shell config
+     * logs it separately, with entries looking like below:
+     *
+     * `/home/bwk/projects/hstr-rs\tcargo test`
+     */
+    let path = format!(".config/hstr-rs/.{}_directories", shell);
+    if !dirs::home_dir()?.join(&path).exists() {
+        return None;
+    }
+    let log = read_from_home(path).ok()?;
+    Some(parse_directory_log(&log))
+}
+
+pub fn parse_directory_log(log: &[String]) -> Vec<(String, String)> {
+    /* Multi-line commands are logged like zsh history logs them,
+     * with a backslash before every newline, so they are joined
+     * back the same way. Lines without a directory are skipped.
+     */
+    zsh::join_continuations(&log.join("\n"))
+        .iter()
+        .filter_map(|entry| entry.split_once('\t'))
+        .map(|(dir, cmd)| (dir.to_string(), cmd.to_string()))
+        .collect()
+}
+
+pub fn commands_under(dir: &Path, directory_history: &[(String, String)]) -> Vec<String> {
+    directory_history
+        .iter()
+        .filter(|(cmd_dir, _)| Path::new(cmd_dir).starts_with(dir))
+        .map(|(_, cmd)| cmd.clone())
+        .collect()
+}
+
+pub mod bash {
+    use regex::{escape, Regex};
+
+    pub fn ignores_space(histcontrol: &str) -> bool {
+        histcontrol
+            .split(':')
+            .any(|value| value == "ignorespace" || value == "ignoreboth")
+    }
+
+    pub fn histignore_patterns(histignore: &str) -> Vec<Regex> {
+        /* HISTIGNORE is a colon-separated list of glob patterns, each
+         * matching a whole command, with a backslash escaping a colon.
+         * `&` stands for the previous history entry, which has
+         * no equivalent here, so it is skipped.
+         */
+        let mut globs = vec![String::new()];
+        let mut chars = histignore.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                ':' => globs.push(String::new()),
+                '\\' => {
+                    let glob = globs.last_mut().unwrap();
+                    glob.push(ch);
+                    glob.extend(chars.next());
+                }
+                ch => globs.last_mut().unwrap().push(ch),
+            }
+        }
+        globs
+            .iter()
+            .filter(|glob| !glob.is_empty() && *glob != "&")
+            .filter_map(|glob| Regex::new(&glob_to_regex(glob)).ok())
+            .collect()
+    }
+
+    fn glob_to_regex(glob: &str) -> String {
+        let chars: Vec<char> = glob.chars().collect();
+        let mut regex = String::from("^(?s:");
+        let mut index = 0;
+        while index < chars.len() {
+            match chars[index] {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                '\\' if index + 1 < chars.len() => {
+                    index += 1;
+                    regex.push_str(&escape(&chars[index].to_string()));
+                }
+                '[' => match bracket_end(&chars, index) {
+                    Some(end) => {
+                        regex.push_str(&bracket_to_class(&chars[index + 1..end]));
+                        index = end;
+                    }
+                    None => regex.push_str(r"\["),
+                },
+                ch => regex.push_str(&escape(&ch.to_string())),
+            }
+            index += 1;
+        }
+        regex + ")$"
+    }
+
+    fn bracket_end(chars: &[char], start: usize) -> Option<usize> {
+        /* A `]` right after the opening `[`, or after `[!`, is a member */
+        let mut index = start + 1;
+        if matches!(chars.get(index), Some('!') | Some('^')) {
+            index += 1;
+        }
+        if chars.get(index) == Some(&']') {
+            index += 1;
+        }
+        (index..chars.len()).find(|&i| chars[i] == ']')
+    }
+
+    fn bracket_to_class(members: &[char]) -> String {
+        let mut class = String::from("[");
+        let members = match members.first() {
+            Some('!') | Some('^') => {
+                class.push('^');
+                &members[1..]
+            }
+            _ => members,
+        };
+        members.iter().for_each(|&ch| {
+            if "\\[]&~^".contains(ch) {
+                class.push('\\');
+            }
+            class.push(ch);
+        });
+        class + "]"
+    }
+
+    /* Caps how many lines an entry can span, so a stray
+     * unterminated quote can't swallow the rest of the history
+     */
+    const MAX_ENTRY_LINES: usize = 32;
+
+    pub fn join_multiline(history: Vec<String>) -> Vec<String> {
+        /* With `lithist` enabled, bash stores multi-line commands
+         * with literal newlines, like below:
+         *
+         * `for i in 1 2 3`
+         * `do echo $i`
+         * `done`
+         *
+         * Lines are joined for as long as the command they make up
+         * is incomplete. Lines that never make up a complete command
+         * are kept as they are.
+         */
+        let mut commands = Vec::new();
+        let mut start = 0;
+        while start < history.len() {
+            let end = (start..history.len())
+                .take(MAX_ENTRY_LINES)
+                .find(|&end| !is_incomplete(&history[start..=end].join("\n")));
+            match end {
+                Some(end) => {
+                    commands.push(history[start..=end].join("\n"));
+                    start = end + 1;
+                }
+                None => {
+                    commands.push(history[start].clone());
+                    start += 1;
+                }
+            }
+        }
+        commands
+    }
+
+    pub fn split_timestamps(history: Vec<String>) -> Vec<(Option<u64>, String)> {
+        /* With HISTTIMEFORMAT set, bash precedes every entry
+         * with a comment holding its timestamp, like below:
+         *
+         * `#1628000000`
+         * `git push origin master`
+         *
+         * All lines up to the next timestamp make up the entry, so
+         * multi-line entries need no guessing. Entries keep the order
+         * of the file, which is written back as it was read; where
+         * interleaved sessions put it out of order, the sort
+         * strategies go by the timestamps instead. Lines before the
+         * first timestamp are handled as if there were no timestamps.
+         */
+        let timestamp = Regex::new(r"^#(\d+)$").unwrap();
+        let mut untimed = Vec::new();
+        let mut timed: Vec<(Option<u64>, Vec<String>)> = Vec::new();
+        for line in history {
+            match timestamp
+                .captures(&line)
+                .and_then(|captures| captures[1].parse().ok())
+            {
+                Some(ts) => timed.push((Some(ts), Vec::new())),
+                None => match timed.last_mut() {
+                    Some((_, lines)) => lines.push(line),
+                    None => untimed.push(line),
+                },
+            }
+        }
+        let timed = timed
+            .into_iter()
+            .filter(|(_, lines)| !lines.is_empty())
+            .map(|(ts, lines)| (ts, lines.join("\n")));
+        join_multiline(untimed)
+            .into_iter()
+            .map(|cmd| (None, cmd))
+            .chain(timed)
+            .collect()
+    }
+
+    fn is_incomplete(command: &str) -> bool {
+        let trimmed = command.trim_end();
+        if trimmed.ends_with('\\') || trimmed.ends_with('|') || trimmed.ends_with("&&") {
+            return true;
+        }
+        let mut quote = None;
+        let mut escaped = false;
+        let mut comment = false;
+        let mut command_start = true;
+        let mut word = String::new();
+        let mut quoted_word = false;
+        let mut depth = 0;
+        let chars: Vec<char> = command.chars().chain(Some('\n')).collect();
+        for (index, &ch) in chars.iter().enumerate() {
+            if comment {
+                comment = ch != '\n';
+                continue;
+            }
+            if escaped {
+                escaped = false;
+                quoted_word = true;
+                continue;
+            }
+            match quote {
+                Some(q) => {
+                    if ch == q {
+                        quote = None;
+                    } else if ch == '\\' && q == '"' {
+                        escaped = true;
+                    }
+                }
+                None => match ch {
+                    '\\' => escaped = true,
+                    '\'' if is_apostrophe(&chars, index) => word.push(ch),
+                    '\'' | '"' => {
+                        quote = Some(ch);
+                        quoted_word = true;
+                    }
+                    '#' if word.is_empty() && !quoted_word => comment = true,
+                    ch if ch.is_whitespace() || ";&|()".contains(ch) => {
+                        if !word.is_empty() || quoted_word {
+                            if command_start && !quoted_word {
+                                depth += block_delta(&word);
+                                command_start = keeps_command_start(&word);
+                            } else {
+                                command_start = false;
+                            }
+                            word.clear();
+                            quoted_word = false;
+                        }
+                        if ch == '\n' || ";&|(".contains(ch) {
+                            command_start = true;
+                        }
+                    }
+                    ch => word.push(ch),
+                },
+            }
+        }
+        quote.is_some() || depth > 0
+    }
+
+    fn is_apostrophe(chars: &[char], index: usize) -> bool {
+        /* bash would take the `'` in `echo it's done` as an opening
+         * quote, but the entry was stored on one line, so joining the
+         * lines after it up to the next `'` would only mangle them
+         */
+        index > 0
+            && chars[index - 1].is_alphanumeric()
+            && matches!(chars.get(index + 1), Some(ch) if ch.is_alphanumeric())
+    }
+
+    fn block_delta(word: &str) -> i32 {
+        match word {
+            "if" | "case" | "for" | "select" | "while" | "until" | "{" => 1,
+            "fi" | "esac" | "done" | "}" => -1,
+            _ => 0,
+        }
+    }
+
+    fn keeps_command_start(word: &str) -> bool {
+        matches!(
+            word,
+            "if" | "then" | "else" | "elif" | "do" | "while" | "until" | "{" | "!" | "time"
+        )
+    }
 }
 
 pub mod zsh {
     use crate::io;
     use regex::Regex;
+    use std::path::Path;
 
-    pub fn process_history() -> String {
-        let history = io::read_as_bytes(".zsh_history").unwrap();
+    pub fn process_history(path: &Path) -> Vec<(Option<Metadata>, String)> {
+        let history = io::read_as_bytes(path).unwrap();
         let unmetafied = unmetafy(history);
-        remove_timestamps(String::from_utf8(unmetafied).unwrap())
+        split_metadata(&String::from_utf8(unmetafied).unwrap())
     }
 
     fn unmetafy(mut bytestring: Vec<u8>) -> Vec<u8> {
@@ -44,13 +461,474 @@ pub mod zsh {
         bytestring
     }
 
-    fn remove_timestamps(history: String) -> String {
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Metadata {
+        pub started: u64,
+        pub duration: u64,
+    }
+
+    pub fn split_timestamps(history: &str) -> Vec<(Option<u64>, String)> {
+        split_metadata(history)
+            .into_iter()
+            .map(|(metadata, cmd)| (metadata.map(|m| m.started), cmd))
+            .collect()
+    }
+
+    pub fn split_metadata(history: &str) -> Vec<(Option<Metadata>, String)> {
         /* The preceding metadata needs to be stripped
          * because zsh history entries look like below:
          *
          * `: 1330648651:0;sudo reboot`
+         *
+         * The first number is when the command started,
+         * the second one is how many seconds it took.
+         */
+        let r = Regex::new(r"^:\s*(\d+):(\d+);").unwrap();
+        join_continuations(history)
+            .into_iter()
+            .map(|x| match r.captures(&x) {
+                Some(captures) => {
+                    let metadata = match (captures[1].parse(), captures[2].parse()) {
+                        (Ok(started), Ok(duration)) => Some(Metadata { started, duration }),
+                        _ => None,
+                    };
+                    (metadata, x[captures[0].len()..].to_string())
+                }
+                None => (None, x),
+            })
+            .collect()
+    }
+
+    pub fn join_continuations(history: &str) -> Vec<String> {
+        /* zsh writes every newline of a multi-line command
+         * with a backslash in front of it, like below:
+         *
+         * `: 1330648651:0;for i in 1 2 3\`
+         * `do echo $i\`
+         * `done`
+         *
+         * A line ending in a backslash that isn't escaped itself
+         * goes on in the next line, so the newline is put back.
+         */
+        let mut records: Vec<String> = Vec::new();
+        let mut continued = false;
+        for line in history.lines() {
+            match records.last_mut() {
+                Some(record) if continued => {
+                    record.pop();
+                    record.push('\n');
+                    record.push_str(line);
+                }
+                _ => records.push(line.to_string()),
+            }
+            let backslashes = line.chars().rev().take_while(|&ch| ch == '\\').count();
+            continued = backslashes % 2 == 1;
+        }
+        records
+    }
+
+    pub fn escape_newlines(command: &str) -> String {
+        command.replace('\n', "\\\n")
+    }
+}
+
+pub mod pwsh {
+    pub const HISTORY: &str = ".local/share/powershell/PSReadLine/ConsoleHost_history.txt";
+
+    pub fn join_continuations(history: Vec<String>) -> Vec<String> {
+        /* PSReadLine stores multi-line commands with every line
+         * but the last one ending in a backtick, like below:
+         *
+         * `Get-ChildItem `
+         * `| Where-Object Length -gt 1kb`
+         *
+         * A backtick followed by a newline is a line continuation,
+         * so the lines are joined with a space.
          */
-        let r = Regex::new(r"^: \d{10}:\d;").unwrap();
-        history.lines().map(|x| r.replace(x, "") + "\n").collect()
+        let mut commands = Vec::new();
+        let mut pending: Option<String> = None;
+        for line in history {
+            let joined = match pending.take() {
+                Some(previous) => previous + " " + line.trim_start(),
+                None => line,
+            };
+            match joined.strip_suffix('`') {
+                Some(continued) => pending = Some(continued.trim_end().to_string()),
+                None => commands.push(joined),
+            }
+        }
+        commands.extend(pending);
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::zsh::Metadata;
+    use super::{Bash, HistorySource, Pwsh, Zsh};
+    use crate::state::Commands;
+    use rstest::rstest;
+    use std::path::{Path, PathBuf};
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[rstest(
+        source,
+        expected,
+        case(
+            Box::new(Bash { path: fixture("bash_history") }),
+            vec!["ls -la", "for i in 1 2 3\ndo echo $i\ndone", "git push origin master"]
+        ),
+        case(
+            Box::new(Zsh { path: fixture("zsh_history") }),
+            vec!["sudo reboot", "echo čevapi", "cd ~/Downloads"]
+        ),
+        case(
+            Box::new(Pwsh { path: fixture("pwsh_history") }),
+            vec!["Get-ChildItem | Where-Object Length -gt 1kb", "Set-Location ~"]
+        )
+    )]
+    fn load(source: Box<dyn HistorySource>, expected: Vec<&str>) {
+        assert_eq!(source.load(), expected);
+    }
+
+    #[rstest(
+        source,
+        case(Box::new(Bash { path: fixture("bash_history") })),
+        case(Box::new(Zsh { path: fixture("zsh_history") })),
+        case(Box::new(Pwsh { path: fixture("pwsh_history") }))
+    )]
+    fn load_without_blank_lines(source: Box<dyn HistorySource>) {
+        let history = source.load();
+        let commands = Commands::new(&history, history.clone());
+        [&commands.sorted, &commands.favorites, &commands.all]
+            .iter()
+            .for_each(|view| {
+                assert!(view
+                    .iter()
+                    .all(|cmd| !cmd.is_empty() && cmd.trim_end() == cmd));
+            });
+    }
+
+    #[rstest(
+        history,
+        expected,
+        case(": 1330648651:0;sudo reboot", vec![(Some(1330648651), "sudo reboot")]),
+        case(": 1330648651:12;make", vec![(Some(1330648651), "make")]),
+        case("ls -la", vec![(None, "ls -la")]),
+        case(
+            ": 1330648651:0;ls\n: 1330648652:0;echo a: 1:0;b",
+            vec![(Some(1330648651), "ls"), (Some(1330648652), "echo a: 1:0;b")]
+        ),
+        case(
+            ": 1330648651:0;for i in 1 2\\\ndo echo $i\\\ndone\n: 1330648652:0;ls",
+            vec![(Some(1330648651), "for i in 1 2\ndo echo $i\ndone"), (Some(1330648652), "ls")]
+        ),
+        case(
+            ": 1330648651:0;echo a\\\\\n: 1330648652:0;ls",
+            vec![(Some(1330648651), "echo a\\\\"), (Some(1330648652), "ls")]
+        ),
+        case("echo 'a\\\nb'\nls", vec![(None, "echo 'a\nb'"), (None, "ls")])
+    )]
+    fn split_zsh_timestamps(history: &str, expected: Vec<(Option<u64>, &str)>) {
+        assert_eq!(
+            super::zsh::split_timestamps(history),
+            expected
+                .into_iter()
+                .map(|(ts, cmd)| (ts, cmd.to_string()))
+                .collect::<Vec<(Option<u64>, String)>>()
+        );
+    }
+
+    #[rstest(
+        history,
+        expected,
+        command,
+        case(": 1330648651:0;sudo reboot", Some((1330648651, 0)), "sudo reboot"),
+        case(": 1330648651:1234;make -j4", Some((1330648651, 1234)), "make -j4"),
+        case(":1330648651:7;ls", Some((1330648651, 7)), "ls"),
+        case(": 1330648651:;ls", None, ": 1330648651:;ls"),
+        case(": 99999999999999999999:0;ls", None, "ls"),
+        case("echo : 1:0;", None, "echo : 1:0;")
+    )]
+    fn split_zsh_metadata(history: &str, expected: Option<(u64, u64)>, command: &str) {
+        let expected = expected.map(|(started, duration)| Metadata { started, duration });
+        assert_eq!(
+            super::zsh::split_metadata(history),
+            vec![(expected, command.to_string())]
+        );
+    }
+
+    #[rstest(
+        histcontrol,
+        expected,
+        case("ignorespace", true),
+        case("ignoreboth", true),
+        case("ignoredups:ignorespace", true),
+        case("ignoredups", false),
+        case("", false)
+    )]
+    fn ignores_space(histcontrol: &str, expected: bool) {
+        assert_eq!(super::bash::ignores_space(histcontrol), expected);
+    }
+
+    #[rstest(
+        histignore,
+        command,
+        expected,
+        case("ls:bg:fg:history", "ls", true),
+        case("ls:bg:fg:history", "ls -la", false),
+        case("ls *", "ls -la", true),
+        case("?s", "ls", true),
+        case("?s", "lss", false),
+        case("[bf]g", "fg", true),
+        case("[!b]g", "bg", false),
+        case("[]x]", "]", true),
+        case("echo\\:*", "echo:spam", true),
+        case("a.b", "axb", false),
+        case("[a", "[a", true),
+        case("&:ls", "&", false),
+        case("", "ls", false),
+        case(" *", " cat secret", true),
+        case("for *", "for i in 1 2\ndo echo $i\ndone", true)
+    )]
+    fn histignore_patterns(histignore: &str, command: &str, expected: bool) {
+        let patterns = super::bash::histignore_patterns(histignore);
+        assert_eq!(patterns.iter().any(|r| r.is_match(command)), expected);
+    }
+
+    #[rstest(
+        entry,
+        expected,
+        case("ls -la", Some("ls -la")),
+        case("ls -la  \t", Some("ls -la")),
+        case(" secret", Some(" secret")),
+        case("echo a\ndone \n", Some("echo a\ndone")),
+        case("", None),
+        case("   ", None)
+    )]
+    fn tidy(entry: &str, expected: Option<&str>) {
+        assert_eq!(super::tidy(entry.to_string()), expected.map(String::from));
+    }
+
+    #[rstest(
+        shell,
+        expected,
+        case("zsh", None),
+        case(
+            "fish",
+            Some("fish is not supported yet. Supported shells are bash, zsh, pwsh, pick one with --shell.")
+        ),
+        case(
+            "N/A",
+            Some("Couldn't detect your shell. Supported shells are bash, zsh, pwsh, pick one with --shell.")
+        )
+    )]
+    fn unsupported(shell: &str, expected: Option<&str>) {
+        assert_eq!(super::unsupported(shell).as_deref(), expected);
+    }
+
+    #[rstest(
+        shell,
+        expected,
+        case("bash", true),
+        case("zsh", true),
+        case("pwsh", true),
+        case("fish", false)
+    )]
+    fn history_source(shell: &str, expected: bool) {
+        let path = super::history_path(shell, None);
+        assert_eq!(super::history_source(shell, path).is_some(), expected);
+    }
+
+    #[rstest(
+        history,
+        expected,
+        case(vec!["ls", "pwd"], vec![(None, "ls"), (None, "pwd")]),
+        case(
+            vec!["#1628000000", "ls", "#1628000001", "pwd"],
+            vec![(Some(1628000000), "ls"), (Some(1628000001), "pwd")]
+        ),
+        case(
+            vec!["make", "#1628000005", "ls", "#1628000001", "pwd", "#1628000003", "cd"],
+            vec![
+                (None, "make"),
+                (Some(1628000005), "ls"),
+                (Some(1628000001), "pwd"),
+                (Some(1628000003), "cd")
+            ]
+        ),
+        case(
+            vec!["#1628000000", "for i in 1 2", "do echo $i", "done", "#1628000001", "# comment"],
+            vec![
+                (Some(1628000000), "for i in 1 2\ndo echo $i\ndone"),
+                (Some(1628000001), "# comment")
+            ]
+        ),
+        case(vec!["#1628000000", "#1628000001", "ls"], vec![(Some(1628000001), "ls")])
+    )]
+    fn split_timestamps(history: Vec<&str>, expected: Vec<(Option<u64>, &str)>) {
+        assert_eq!(
+            super::bash::split_timestamps(history.iter().map(|&x| x.into()).collect()),
+            expected
+                .into_iter()
+                .map(|(ts, cmd)| (ts, cmd.to_string()))
+                .collect::<Vec<(Option<u64>, String)>>()
+        );
+    }
+
+    #[rstest(
+        history,
+        expected,
+        case(vec!["ls", "pwd"], vec!["ls", "pwd"]),
+        case(
+            vec!["for i in 1 2 3", "do echo $i", "done", "ls"],
+            vec!["for i in 1 2 3\ndo echo $i\ndone", "ls"]
+        ),
+        case(
+            vec!["while read line; do", "  echo $line", "done < spam.txt"],
+            vec!["while read line; do\n  echo $line\ndone < spam.txt"]
+        ),
+        case(
+            vec!["if true; then", "  echo yes", "else", "  echo no", "fi"],
+            vec!["if true; then\n  echo yes\nelse\n  echo no\nfi"]
+        ),
+        case(
+            vec!["case $x in", "  a) echo a;;", "esac", "pwd"],
+            vec!["case $x in\n  a) echo a;;\nesac", "pwd"]
+        ),
+        case(
+            vec!["spam() {", "  echo eggs", "}"],
+            vec!["spam() {\n  echo eggs\n}"]
+        ),
+        case(vec!["echo 'multi", "line'", "ls"], vec!["echo 'multi\nline'", "ls"]),
+        case(vec!["cargo build \\", "  --release"], vec!["cargo build \\\n  --release"]),
+        case(vec!["cat spam |", "grep eggs"], vec!["cat spam |\ngrep eggs"]),
+        case(vec!["echo do", "echo if", "echo 'done'"], vec!["echo do", "echo if", "echo 'done'"]),
+        case(vec!["echo hi # don't", "ls"], vec!["echo hi # don't", "ls"]),
+        case(vec!["echo \"it's\"", "ls"], vec!["echo \"it's\"", "ls"]),
+        case(vec!["echo it's", "ls", "pwd"], vec!["echo it's", "ls", "pwd"]),
+        case(
+            vec!["echo it's done", "ls", "git commit -m 'wip'"],
+            vec!["echo it's done", "ls", "git commit -m 'wip'"]
+        ),
+        case(
+            vec!["for f in *; do", "  echo don't", "done"],
+            vec!["for f in *; do\n  echo don't\ndone"]
+        ),
+        case(vec!["echo 'it''s", "done'"], vec!["echo 'it''s\ndone'"])
+    )]
+    fn join_multiline(history: Vec<&str>, expected: Vec<&str>) {
+        assert_eq!(
+            super::bash::join_multiline(history.iter().map(|&x| x.into()).collect()),
+            expected
+        );
+    }
+
+    #[rstest(
+        history,
+        expected,
+        case(vec!["ls", "pwd"], vec!["ls", "pwd"]),
+        case(
+            vec!["Get-ChildItem `", "  | Where-Object Length -gt 1kb", "pwd"],
+            vec!["Get-ChildItem | Where-Object Length -gt 1kb", "pwd"]
+        ),
+        case(
+            vec!["foreach ($x in 1..3) {`", "echo $x`", "}"],
+            vec!["foreach ($x in 1..3) { echo $x }"]
+        ),
+        case(vec!["echo `", ""], vec!["echo "]),
+        case(vec!["echo spam `"], vec!["echo spam"])
+    )]
+    fn join_continuations(history: Vec<&str>, expected: Vec<&str>) {
+        assert_eq!(
+            super::pwsh::join_continuations(history.iter().map(|&x| x.into()).collect()),
+            expected
+        );
+    }
+
+    #[rstest(
+        shell,
+        expected,
+        case("bash", ".bash_history"),
+        case("zsh", ".zsh_history"),
+        case("pwsh", ".local/share/powershell/PSReadLine/ConsoleHost_history.txt")
+    )]
+    fn history_file(shell: &str, expected: &str) {
+        assert_eq!(super::history_file(shell), expected);
+    }
+
+    #[rstest(
+        shell,
+        histfile,
+        expected,
+        case("bash", None, ".bash_history"),
+        case("bash", Some(""), ".bash_history"),
+        case("bash", Some("/var/log/history"), "/var/log/history"),
+        case("zsh", Some("/home/bwk/.histfile"), "/home/bwk/.histfile"),
+        case(
+            "pwsh",
+            Some("/home/bwk/.histfile"),
+            ".local/share/powershell/PSReadLine/ConsoleHost_history.txt"
+        )
+    )]
+    fn history_path(shell: &str, histfile: Option<&str>, expected: &str) {
+        assert_eq!(
+            super::history_path(shell, histfile.map(String::from)),
+            PathBuf::from(expected)
+        );
+    }
+
+    #[test]
+    fn parse_directory_log() {
+        let log = [
+            "/home/bwk/hstr-rs\tcargo test",
+            "/home/bwk\tfor i in 1 2 3\\",
+            "do echo $i\\",
+            "done",
+            "no directory",
+            "/tmp\tprintf 'a\\tb'",
+        ]
+        .iter()
+        .map(|&x| x.into())
+        .collect::<Vec<String>>();
+        let expected = [
+            ("/home/bwk/hstr-rs", "cargo test"),
+            ("/home/bwk", "for i in 1 2 3\ndo echo $i\ndone"),
+            ("/tmp", "printf 'a\\tb'"),
+        ]
+        .iter()
+        .map(|&(d, c)| (d.to_string(), c.to_string()))
+        .collect::<Vec<(String, String)>>();
+        assert_eq!(super::parse_directory_log(&log), expected);
+    }
+
+    #[rstest(
+        dir,
+        expected,
+        case("/home/bwk/hstr-rs", vec!["cargo test", "git push origin master", "cargo test"]),
+        case("/home/bwk/hstr-rs/src", vec!["git push origin master"]),
+        case("/home/bwk/hstr", vec!["make -j4"]),
+        case("/tmp", vec![]),
+        case("/home", vec!["cargo test", "git push origin master", "cargo test", "make -j4"])
+    )]
+    fn commands_under(dir: &str, expected: Vec<&str>) {
+        let directory_history = [
+            ("/home/bwk/hstr-rs", "cargo test"),
+            ("/home/bwk/hstr-rs/src", "git push origin master"),
+            ("/home/bwk/hstr-rs", "cargo test"),
+            ("/home/bwk/hstr", "make -j4"),
+        ]
+        .iter()
+        .map(|&(d, c)| (d.to_string(), c.to_string()))
+        .collect::<Vec<(String, String)>>();
+        assert_eq!(
+            super::commands_under(Path::new(dir), &directory_history),
+            expected
+        );
     }
 }
@@ -1,9 +1,34 @@
+use crate::sort;
+use crate::state::{State, View};
+use itertools::Itertools;
 use libc::{ioctl, TIOCSTI};
 use std::{
-    fs::{create_dir_all, write, File},
-    io::{BufRead, BufReader, Error, Read},
+    env,
+    fs::{canonicalize, copy, create_dir_all, read_dir, remove_file, rename, File, OpenOptions},
+    io::{stdin, BufRead, BufReader, Error, ErrorKind, Read, Write},
+    os::unix::fs::OpenOptionsExt,
     path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use strum_macros::{AsRefStr, EnumString};
+
+/* How the selected command gets to the prompt */
+#[derive(AsRefStr, Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum PasteBackend {
+    Tiocsti,
+    Tmux,
+    Clipboard,
+}
+
+/* Tried in order, the first one that is installed and works wins */
+const CLIPBOARDS: &[&[&str]] = &[
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["pbcopy"],
+];
 
 pub fn read_as_bytes(path: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
     let home = dirs::home_dir().unwrap();
@@ -25,6 +50,15 @@ pub fn read_from_home(path: impl AsRef<Path>) -> Result<Vec<String>, Error> {
     }
 }
 
+pub fn read_lines(path: &Path) -> Result<Vec<String>, Error> {
+    /* `path` is relative to the current directory, `-` is stdin */
+    if path == Path::new("-") {
+        stdin().lock().lines().collect()
+    } else {
+        read_file(path.to_path_buf())
+    }
+}
+
 fn read_file(target: PathBuf) -> Result<Vec<String>, Error> {
     let file = File::open(target)?;
     let reader = BufReader::new(file);
@@ -33,10 +67,112 @@ fn read_file(target: PathBuf) -> Result<Vec<String>, Error> {
 
 pub fn write_to_home(path: impl AsRef<Path>, thing: &[String]) -> Result<(), Error> {
     let home = dirs::home_dir().unwrap();
-    let target = home.join(path);
-    ensure_target_existence(&target)?;
-    write(target, thing.join("\n"))?;
-    Ok(())
+    write_file(&home.join(path), &as_lines(thing))
+}
+
+fn as_lines(thing: &[String]) -> String {
+    /* Every line ends with a newline, the last one too,
+     * so appending a line doesn't touch the one before it
+     */
+    thing.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+fn write_file(target: &Path, contents: &str) -> Result<(), Error> {
+    /* The contents go to a file next to the target, which is then
+     * renamed over it, so a crash halfway leaves the old file intact.
+     * A symlink is followed, so that the link itself stays in place.
+     */
+    ensure_target_existence(target)?;
+    let target = canonicalize(target)?;
+    let temp = temp_path(&target);
+    let written = write_temp(&temp, &target, contents).and_then(|_| rename(&temp, &target));
+    if written.is_err() {
+        let _ = remove_file(&temp);
+    }
+    written
+}
+
+fn write_temp(temp: &Path, target: &Path, contents: &str) -> Result<(), Error> {
+    /* History is private, so the file is never readable by others,
+     * not even before it gets the permissions of the target
+     */
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(temp)?;
+    file.set_permissions(target.metadata()?.permissions())?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
+
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    target.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+pub fn back_up(
+    path: impl AsRef<Path>,
+    backup_dir: Option<&Path>,
+    now: u64,
+) -> Result<Option<PathBuf>, Error> {
+    /* `path` is relative to home directory. The copy goes next to it,
+     * unless another directory is given, and is skipped if there is
+     * one from the last day already.
+     */
+    let home = dirs::home_dir().unwrap();
+    let source = home.join(path);
+    let name = match source.file_name() {
+        Some(name) if source.exists() => name.to_string_lossy().into_owned(),
+        _ => return Ok(None),
+    };
+    let dir = match backup_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => source.parent().unwrap().to_path_buf(),
+    };
+    create_dir_all(&dir)?;
+    for entry in read_dir(&dir)? {
+        let file_name = entry?.file_name();
+        if is_recent_backup(&file_name.to_string_lossy(), &name, now) {
+            return Ok(None);
+        }
+    }
+    let target = dir.join(format!("{}.hstr.{}.bak", name, now));
+    copy(&source, &target)?;
+    Ok(Some(target))
+}
+
+fn is_recent_backup(file_name: &str, name: &str, now: u64) -> bool {
+    const DAY: u64 = 24 * 60 * 60;
+    file_name
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix(".hstr."))
+        .and_then(|rest| rest.strip_suffix(".bak"))
+        .and_then(|timestamp| timestamp.parse::<u64>().ok())
+        .is_some_and(|timestamp| now.saturating_sub(timestamp) < DAY)
+}
+
+pub fn export(path: &Path, state: &State, shebang: Option<&str>) -> Result<(), Error> {
+    /* Unlike the other files, `path` is relative to the current directory,
+     * and `-` is stdout. Favorite sequences are exported one command per line.
+     */
+    let script = shebang
+        .map(|shell| format!("#!/usr/bin/env {}", shell))
+        .into_iter()
+        .chain(
+            state
+                .commands(state.view)
+                .iter()
+                .map(|cmd| state.assemble(cmd, "\n")),
+        )
+        .map(|line| line + "\n")
+        .collect::<String>();
+    if path == Path::new("-") {
+        print!("{}", script);
+        Ok(())
+    } else {
+        write_file(path, &script)
+    }
 }
 
 fn ensure_target_existence(target: &Path) -> Result<(), Error> {
@@ -55,11 +191,190 @@ pub fn echo(command: String) {
     }
 }
 
+pub fn paste_with(backend: PasteBackend, text: &str) -> Result<(), Error> {
+    match backend {
+        PasteBackend::Tiocsti => {
+            echo(text.to_string());
+            Ok(())
+        }
+        PasteBackend::Tmux => send_keys(text),
+        PasteBackend::Clipboard => copy_with(CLIPBOARDS, text),
+    }
+}
+
+fn send_keys(text: &str) -> Result<(), Error> {
+    /* -l types the text as it is, rather than reading it as key names */
+    let mut tmux = Command::new("tmux");
+    tmux.args(["send-keys", "-l"]);
+    if let Ok(pane) = env::var("TMUX_PANE") {
+        tmux.args(["-t", &pane]);
+    }
+    let status = tmux.arg("--").arg(text).stderr(Stdio::null()).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other("tmux send-keys failed"))
+    }
+}
+
+fn copy_with(tools: &[&[&str]], text: &str) -> Result<(), Error> {
+    /* A tool that can't run here, e.g. xclip without a display,
+     * may exit before reading, which breaks the pipe. It's still
+     * waited for, so it doesn't linger, and the next one is tried.
+     */
+    for tool in tools {
+        let child = Command::new(tool[0])
+            .args(&tool[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+        let written = child.stdin.take().unwrap().write_all(text.as_bytes());
+        let succeeded = matches!(child.wait(), Ok(status) if status.success());
+        if written.is_ok() && succeeded {
+            return Ok(());
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        "No working clipboard tool: wl-copy, xclip, xsel or pbcopy",
+    ))
+}
+
+pub fn edit_in_editor(command: &str) -> Result<Option<String>, Error> {
+    let editor = editor(env::var("VISUAL").ok(), env::var("EDITOR").ok());
+    /* A file left over by a crashed run with the same pid must not clash */
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or_default();
+    let name = format!("hstr-rs.{}.{}.sh", std::process::id(), nanos);
+    let path = env::temp_dir().join(name);
+    let edited = edit_with(&editor, &path, command);
+    let _ = remove_file(&path);
+    edited
+}
+
+fn editor(visual: Option<String>, editor: Option<String>) -> String {
+    visual
+        .into_iter()
+        .chain(editor)
+        .find(|x| !x.trim().is_empty())
+        .unwrap_or_else(|| String::from("vi"))
+}
+
+fn edit_with(editor: &str, path: &Path, command: &str) -> Result<Option<String>, Error> {
+    /* Quitting the editor without saving leaves the file as it
+     * was written, which cancels, as does a failing editor.
+     * Timestamps may be too coarse to tell, so the contents do.
+     */
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    let written = format!("{}\n", command);
+    file.write_all(written.as_bytes())?;
+    drop(file);
+    let mut words = editor.split_whitespace();
+    let mut child = Command::new(words.next().unwrap_or("vi"));
+    child.args(words).arg(path);
+    /* With --print, stdout is a pipe, but the editor needs the terminal */
+    if let Ok(tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") {
+        child.stdin(tty.try_clone()?).stdout(tty);
+    }
+    let status = child.status()?;
+    if !status.success() {
+        return Ok(None);
+    }
+    let edited = std::fs::read_to_string(path)?;
+    if edited == written {
+        return Ok(None);
+    }
+    let edited = edited.trim_end_matches('\n');
+    if edited.trim().is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(edited.to_string()))
+    }
+}
+
+pub fn bracketed(command: &str) -> String {
+    /* The shell takes everything between the markers as a single
+     * paste, so newlines in the command don't run it line by line
+     */
+    format!("\x1b[200~{}\x1b[201~", command)
+}
+
+pub fn matches_as_json(state: &State) -> String {
+    let matches = state
+        .commands(state.view)
+        .iter()
+        .map(|cmd| {
+            format!(
+                "{{\"command\":{},\"view\":\"{}\",\"is_favorite\":{}}}",
+                json_string(cmd),
+                state.view.as_ref(),
+                state.cmd_in_fav(cmd)
+            )
+        })
+        .collect::<Vec<String>>();
+    format!("[{}]", matches.join(","))
+}
+
+pub fn stats(state: &State) -> String {
+    let mut lines = vec![
+        format!("History entries: {}", state.raw_history.len()),
+        format!(
+            "Unique commands: {}",
+            state.raw_history.iter().unique().count()
+        ),
+        format!("Favorites: {}", state.commands(View::Favorites).len()),
+        String::new(),
+        String::from("Most frequent:"),
+    ];
+    lines.extend(
+        sort::most_frequent(&state.raw_history, 10)
+            .into_iter()
+            .map(|(cmd, count)| format!("{:>6}  {}", count, cmd)),
+    );
+    lines.join("\n")
+}
+
+pub fn profile(timings: &[(&str, Duration)]) -> String {
+    let total: Duration = timings.iter().map(|(_, took)| *took).sum();
+    timings
+        .iter()
+        .chain(std::iter::once(&("total", total)))
+        .map(|(stage, took)| format!("{:<16}{:>10.3} ms", stage, took.as_secs_f64() * 1000.0))
+        .join("\n")
+}
+
+fn json_string(string: &str) -> String {
+    let mut escaped = String::from("\"");
+    string.chars().for_each(|ch| match ch {
+        '"' => escaped.push_str("\\\""),
+        '\\' => escaped.push_str("\\\\"),
+        '\n' => escaped.push_str("\\n"),
+        '\r' => escaped.push_str("\\r"),
+        '\t' => escaped.push_str("\\t"),
+        ch if ch.is_control() => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+        ch => escaped.push(ch),
+    });
+    escaped.push('"');
+    escaped
+}
+
 pub fn print_config(shell: &str) {
     match shell {
         "bash" => print_bash_config(),
         "zsh" => print_zsh_config(),
-        _ => eprintln!("Available options: bash, zsh"),
+        "pwsh" => print_pwsh_config(),
+        _ => eprintln!("Available options: bash, zsh, pwsh"),
     }
 }
 
@@ -72,3 +387,274 @@ fn print_zsh_config() {
     let zsh_config = include_str!("config/zsh");
     println!("{}", zsh_config);
 }
+
+fn print_pwsh_config() {
+    let pwsh_config = include_str!("config/pwsh");
+    println!("{}", pwsh_config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasteBackend;
+    use crate::state::{fixtures::*, State, View};
+    use rstest::rstest;
+    use std::time::Duration;
+
+    #[rstest(
+        string,
+        expected,
+        case("ls -la", r#""ls -la""#),
+        case(r#"echo "spam""#, r#""echo \"spam\"""#),
+        case(r"printf 'a\tb'", r#""printf 'a\\tb'""#),
+        case(
+            "for i in 1 2\ndo echo $i; done",
+            r#""for i in 1 2\ndo echo $i; done""#
+        ),
+        case("echo \u{1b}[31m", r#""echo \u001b[31m""#)
+    )]
+    fn json_string(string: &str, expected: &str) {
+        assert_eq!(super::json_string(string), expected);
+    }
+
+    #[rstest(
+        thing,
+        expected,
+        case(vec![], ""),
+        case(vec!["ls"], "ls\n"),
+        case(vec!["ls", "cd /tmp"], "ls\ncd /tmp\n")
+    )]
+    fn as_lines(thing: Vec<&str>, expected: &str) {
+        let thing = thing.into_iter().map(String::from).collect::<Vec<String>>();
+        assert_eq!(super::as_lines(&thing), expected);
+    }
+
+    #[rstest(
+        shebang,
+        expected,
+        case(None, "ls -la\necho ';;;' x\ncargo fmt\ncargo test\n"),
+        case(
+            Some("bash"),
+            "#!/usr/bin/env bash\nls -la\necho ';;;' x\ncargo fmt\ncargo test\n"
+        )
+    )]
+    fn export(shebang: Option<&str>, expected: &str, mut fake_state: State) {
+        let path = std::env::temp_dir()
+            .join(format!("hstr-rs-export-{}", std::process::id()))
+            .join(shebang.unwrap_or("plain"))
+            .join("export.sh");
+        fake_state.add_or_rm_fav(String::from("cargo fmt ;;; cargo test"));
+        fake_state.view = View::All;
+        fake_state.commands.all = vec![
+            String::from("ls -la"),
+            String::from("echo ';;;' x"),
+            String::from("cargo fmt ;;; cargo test"),
+        ];
+        super::export(&path, &fake_state, shebang).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[rstest(link, case(false), case(true))]
+    fn write_file(link: bool) {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        let dir = std::env::temp_dir()
+            .join(format!("hstr-rs-write-{}", std::process::id()))
+            .join(link.to_string());
+        let history = dir.join(".bash_history");
+        let target = if link {
+            dir.join("link")
+        } else {
+            history.clone()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&history, "ls\nmake\n").unwrap();
+        std::fs::set_permissions(&history, std::fs::Permissions::from_mode(0o600)).unwrap();
+        if link {
+            symlink(&history, &target).unwrap();
+        }
+        super::write_file(&target, "ls\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&history).unwrap(), "ls\n");
+        assert_eq!(
+            history.metadata().unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert!(
+            std::fs::symlink_metadata(&target)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+                == link
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1 + link as usize);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[rstest(
+        file_name,
+        expected,
+        case(".bash_history.hstr.1000000.bak", true),
+        case(".bash_history.hstr.999000.bak", true),
+        case(".bash_history.hstr.900000.bak", false),
+        case(".zsh_history.hstr.1000000.bak", false),
+        case(".bash_history.hstr.spam.bak", false),
+        case(".bash_history", false)
+    )]
+    fn is_recent_backup(file_name: &str, expected: bool) {
+        assert_eq!(
+            super::is_recent_backup(file_name, ".bash_history", 1_000_000),
+            expected
+        );
+    }
+
+    #[rstest(
+        visual,
+        editor,
+        expected,
+        case(Some("code --wait"), Some("vim"), "code --wait"),
+        case(Some(""), Some("vim"), "vim"),
+        case(None, None, "vi")
+    )]
+    fn editor_from_env(visual: Option<&str>, editor: Option<&str>, expected: &str) {
+        assert_eq!(
+            super::editor(visual.map(String::from), editor.map(String::from)),
+            expected
+        );
+    }
+
+    #[rstest(
+        editor,
+        expected,
+        case("sed -i s/ls/pwd/", Some("pwd -la")),
+        case("true", None),
+        case("sed -i s/spam/eggs/", None),
+        case("false", None),
+        case("sed -i d", None)
+    )]
+    fn edit_command(editor: &str, expected: Option<&str>) {
+        let path = std::env::temp_dir().join(format!(
+            "hstr-rs-edit-{}-{}",
+            std::process::id(),
+            editor.replace(|c: char| !c.is_alphanumeric(), "")
+        ));
+        let edited = super::edit_with(editor, &path, "ls -la").unwrap();
+        assert_eq!(edited.as_deref(), expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[rstest(
+        tools,
+        size,
+        expected,
+        case(vec![vec!["cat"]], 10, true),
+        case(vec![vec!["hstr-rs-no-such-tool"], vec!["cat"]], 10, true),
+        case(vec![vec!["false"], vec!["cat"]], 10, true),
+        case(vec![vec!["true"], vec!["cat"]], 1 << 20, true),
+        case(vec![vec!["true"]], 1 << 20, false),
+        case(vec![vec!["false"]], 10, false),
+        case(vec![], 10, false)
+    )]
+    fn copy_with(tools: Vec<Vec<&str>>, size: usize, expected: bool) {
+        let tools: Vec<&[&str]> = tools.iter().map(Vec::as_slice).collect();
+        assert_eq!(
+            super::copy_with(&tools, &"x".repeat(size)).is_ok(),
+            expected
+        );
+    }
+
+    #[rstest()]
+    fn back_up() {
+        let dir = std::env::temp_dir().join(format!("hstr-rs-backup-{}", std::process::id()));
+        let history = dir.join(".bash_history");
+        let backups = dir.join("backups");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&history, "ls\n").unwrap();
+        let backup = super::back_up(&history, Some(&backups), 1_000_000).unwrap();
+        assert_eq!(backup, Some(backups.join(".bash_history.hstr.1000000.bak")));
+        assert_eq!(std::fs::read_to_string(backup.unwrap()).unwrap(), "ls\n");
+        assert_eq!(
+            super::back_up(&history, Some(&backups), 1_000_060).unwrap(),
+            None
+        );
+        assert!(super::back_up(&history, Some(&backups), 2_000_000)
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            super::back_up(dir.join("nothing"), None, 1_000_000).unwrap(),
+            None
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[rstest(
+        command,
+        expected,
+        case("ls -la", "\x1b[200~ls -la\x1b[201~"),
+        case("cd /tmp\nls", "\x1b[200~cd /tmp\nls\x1b[201~")
+    )]
+    fn bracketed(command: &str, expected: &str) {
+        assert_eq!(super::bracketed(command), expected);
+    }
+
+    #[rstest(
+        value,
+        expected,
+        case("tiocsti", PasteBackend::Tiocsti),
+        case("tmux", PasteBackend::Tmux),
+        case("clipboard", PasteBackend::Clipboard)
+    )]
+    fn parse_paste_backend(value: &str, expected: PasteBackend) {
+        assert_eq!(value.parse::<PasteBackend>().unwrap(), expected);
+    }
+
+    #[rstest()]
+    fn profile() {
+        let timings = [
+            ("load history", Duration::from_micros(12_500)),
+            ("first search", Duration::from_millis(3)),
+        ];
+        assert_eq!(
+            super::profile(&timings),
+            concat!(
+                "load history        12.500 ms\n",
+                "first search         3.000 ms\n",
+                "total               15.500 ms"
+            )
+        );
+    }
+
+    #[rstest()]
+    fn stats(mut fake_state: State) {
+        fake_state.raw_history = ["ls", "make", "ls", "ls", "make", "cd"]
+            .iter()
+            .map(|cmd| cmd.to_string())
+            .collect();
+        fake_state.add_or_rm_fav(String::from("make"));
+        assert_eq!(
+            super::stats(&fake_state),
+            concat!(
+                "History entries: 6\n",
+                "Unique commands: 3\n",
+                "Favorites: 1\n",
+                "\n",
+                "Most frequent:\n",
+                "     3  ls\n",
+                "     2  make\n",
+                "     1  cd"
+            )
+        );
+    }
+
+    #[rstest()]
+    fn matches_as_json(mut fake_state: State) {
+        fake_state.query = String::from("cat");
+        fake_state.add_or_rm_fav(String::from("cat SPAM"));
+        fake_state.search();
+        assert_eq!(
+            super::matches_as_json(&fake_state),
+            concat!(
+                r#"[{"command":"cat spam","view":"sorted","is_favorite":false},"#,
+                r#"{"command":"cat SPAM","view":"sorted","is_favorite":true}]"#
+            )
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use crate::state::{Commands, SearchMode, State, View};
+
+pub mod favorites;
+pub mod hstr;
+pub mod io;
+pub mod sort;
+pub mod state;
+#[cfg(feature = "tui")]
+pub mod ui;
+
+pub fn search(history: &[String], query: &str, search_mode: SearchMode) -> Vec<String> {
+    /* Exact and loose matches are ranked by where the query matches:
+     * at the start of the command, then at the start of a word, then
+     * anywhere. Fuzzy matches are ranked by score, and regex matches
+     * aren't ranked. Ties keep the order of the sorted view: most
+     * frequent first, then most recent first.
+     */
+    let mut state = State::from_commands(
+        "",
+        query,
+        history.to_vec(),
+        Commands::new(history, Vec::new()),
+    );
+    state.search_mode = search_mode;
+    state.search();
+    state.commands(View::Sorted).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::fixtures::*;
+    use rstest::rstest;
+
+    #[rstest(
+        query,
+        search_mode,
+        expected,
+        case("cat", SearchMode::Exact, vec!["cat SPAM", "cat spam"]),
+        case("^ls", SearchMode::Regex, vec!["lspci", "lsusb", "ls -la"]),
+        case("hwk", SearchMode::Fuzzy, vec!["cd /home/bwk/", "nano .github/workflows/build.yml"]),
+        case("spam eggs", SearchMode::Exact, vec![]),
+        case("catspam", SearchMode::Loose, vec!["cat SPAM", "cat spam"])
+    )]
+    fn search(
+        query: &str,
+        search_mode: SearchMode,
+        expected: Vec<&str>,
+        fake_history: Vec<String>,
+    ) {
+        assert_eq!(super::search(&fake_history, query, search_mode), expected);
+    }
+
+    #[test]
+    fn search_ranks_by_frequency() {
+        let history = [
+            "git push",
+            "cargo test",
+            "git pull",
+            "cargo test",
+            "git push",
+            "git push",
+        ]
+        .iter()
+        .map(|&x| x.into())
+        .collect::<Vec<String>>();
+        assert_eq!(
+            super::search(&history, "git", SearchMode::Exact),
+            vec!["git push", "git pull"]
+        );
+        assert_eq!(
+            super::search(&history, "", SearchMode::Exact),
+            vec!["git push", "cargo test", "git pull"]
+        );
+    }
+
+    #[test]
+    fn search_ranks_by_position_first() {
+        let history = ["echo test", "echo test", "test -f x"]
+            .iter()
+            .map(|&x| x.into())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            super::search(&history, "test", SearchMode::Exact),
+            vec!["test -f x", "echo test"]
+        );
+        assert_eq!(
+            super::search(&history, "test", SearchMode::Regex),
+            vec!["echo test", "test -f x"]
+        );
+    }
+}
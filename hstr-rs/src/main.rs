@@ -1,119 +1,607 @@
-use crate::state::View;
-use crate::ui::Direction;
+use hstr_rs::sort::{self, SortMode};
+use hstr_rs::state::{SearchMode, View};
+use hstr_rs::ui::Direction;
+use hstr_rs::{favorites, hstr, io, state, ui};
 
 #[cfg(test)]
 use fake_ncurses as nc;
 #[cfg(not(test))]
 use ncurses as nc;
 
-use structopt::StructOpt;
-
-mod hstr;
-mod io;
-mod sort;
-mod state;
-mod ui;
+use regex::Regex;
+use std::env;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::{
+    clap::{self, AppSettings},
+    StructOpt,
+};
 
+const CTRL_A: u32 = 1;
+const CTRL_B: u32 = 2;
+const CTRL_D: u32 = 4;
 const CTRL_E: u32 = 5;
 const CTRL_F: u32 = 6;
+const CTRL_G: u32 = 7;
 const TAB: u32 = 9;
 const ENTER: u32 = 10;
+const CTRL_K: u32 = 11;
+const CTRL_L: u32 = 12;
+const CTRL_N: u32 = 14;
+const CTRL_O: u32 = 15;
+const CTRL_P: u32 = 16;
+const CTRL_R: u32 = 18;
 const CTRL_T: u32 = 20;
+const CTRL_U: u32 = 21;
+const CTRL_V: u32 = 22;
+const CTRL_W: u32 = 23;
+const CTRL_X: u32 = 24;
+const CTRL_Y: u32 = 25;
 const ESC: u32 = 27;
+const CTRL_BRACKET: u32 = 29;
 const CTRL_SLASH: u32 = 31;
-const Y: i32 = b'y' as i32;
+const QUESTION_MARK: u32 = '?' as u32;
+
+const SUBCOMMANDS: &[&str] = &["search", "config", "stats", "import", "export", "delete"];
+
+const SETTINGS: &str = ".config/hstr-rs/.settings";
+const IGNORE: &str = ".config/hstr-rs/.ignore";
+const REDACT: &str = ".config/hstr-rs/.redact";
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
     query: Vec<String>,
-    #[structopt(name = "show-config", long)]
-    show_config: Option<String>,
+    #[structopt(name = "hide-help", long)]
+    hide_help: bool,
+    #[structopt(name = "regex", long, global = true, conflicts_with = "fuzzy")]
+    regex: bool,
+    #[structopt(name = "fuzzy", long, global = true)]
+    fuzzy: bool,
+    #[structopt(name = "loose", long, global = true, conflicts_with_all = &["regex", "fuzzy"])]
+    loose: bool,
+    #[structopt(name = "min-length", long, global = true)]
+    min_length: Option<usize>,
+    #[structopt(name = "max-length", long, global = true)]
+    max_length: Option<usize>,
+    #[structopt(name = "smart-case", long, global = true)]
+    smart_case: bool,
+    #[structopt(name = "profile", long, global = true)]
+    profile: bool,
+    #[structopt(name = "no-persist", long)]
+    no_persist: bool,
+    #[structopt(name = "status-format", long)]
+    status_format: Option<String>,
+    #[structopt(name = "prompt-format", long)]
+    prompt_format: Option<String>,
+    #[structopt(name = "margin", long, default_value = "0")]
+    margin: i32,
+    #[structopt(name = "timestamps", long)]
+    timestamps: Option<ui::TimeFormat>,
+    #[structopt(name = "max-rows", long)]
+    max_rows: Option<usize>,
+    #[structopt(name = "navigation", long, default_value = "paged")]
+    navigation: ui::Navigation,
+    #[structopt(name = "shell", long, global = true, possible_values = hstr::SHELLS)]
+    shell: Option<String>,
+    #[structopt(name = "history-file", long, global = true, parse(from_os_str))]
+    history_file: Option<PathBuf>,
+    #[structopt(
+        name = "pin-favorites",
+        long,
+        global = true,
+        conflicts_with = "no-favorites"
+    )]
+    pin_favorites: bool,
+    #[structopt(name = "no-favorites", long, global = true)]
+    no_favorites: bool,
+    #[structopt(name = "mask", long, default_value = "*")]
+    mask: char,
+    #[structopt(name = "read-only", long, global = true)]
+    read_only: bool,
+    #[structopt(name = "ignore-space", long, global = true)]
+    ignore_space: bool,
+    #[structopt(name = "edit-before-paste", long)]
+    edit_before_paste: bool,
+    #[structopt(name = "bracketed-paste", long)]
+    bracketed_paste: bool,
+    #[structopt(name = "paste-with", long)]
+    paste_with: Option<io::PasteBackend>,
+    #[structopt(name = "sticky", long, conflicts_with = "print")]
+    sticky: bool,
+    #[structopt(name = "no-execute", long)]
+    no_execute: bool,
+    #[structopt(name = "print", long)]
+    print: bool,
+    #[structopt(name = "wrap", long)]
+    wrap: bool,
+    #[structopt(name = "vim", long)]
+    vim: bool,
+    #[structopt(name = "quick-select", long)]
+    quick_select: bool,
+    #[structopt(name = "sort", long, global = true)]
+    sort: Option<SortMode>,
+    #[structopt(name = "view", long, possible_values = state::VIEWS)]
+    view: Option<View>,
+    #[structopt(name = "backup-dir", long, global = true, parse(from_os_str))]
+    backup_dir: Option<PathBuf>,
 }
 
-fn main() -> Result<(), std::io::Error> {
-    let opt = Opt::from_args();
-    if let Some(shell) = opt.show_config {
-        io::print_config(&shell);
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(about = "Prints the matching commands without starting the UI")]
+    Search {
+        query: Vec<String>,
+        #[structopt(name = "json", long)]
+        json: bool,
+    },
+    #[structopt(about = "Deletes the matching commands from the history")]
+    Delete {
+        pattern: Vec<String>,
+        #[structopt(name = "yes", long)]
+        yes: bool,
+    },
+    #[structopt(about = "Prints the shell configuration")]
+    Config {
+        #[structopt(name = "SHELL", possible_values = hstr::SHELLS)]
+        shell: String,
+    },
+    #[structopt(about = "Prints history and favorites counts")]
+    Stats,
+    #[structopt(about = "Adds the commands of a file to the favorites")]
+    Import {
+        #[structopt(name = "input", long, parse(from_os_str))]
+        input: PathBuf,
+    },
+    #[structopt(about = "Writes the commands of a view to a file")]
+    Export {
+        #[structopt(name = "output", long, parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(name = "view", long, default_value = "favorites", possible_values = state::VIEWS)]
+        view: View,
+        #[structopt(name = "query", long)]
+        query: Option<String>,
+        #[structopt(name = "shebang", long)]
+        shebang: bool,
+    },
+}
+
+fn main() -> Result<(), Error> {
+    let opt = parse_args(env::args().collect());
+    if let Some(Command::Config { shell }) = &opt.command {
+        io::print_config(shell);
         return Ok(());
     }
 
+    let shell = opt
+        .shell
+        .as_deref()
+        .unwrap_or_else(|| setenv::get_shell().get_name());
+    if let Some(message) = hstr::unsupported(shell) {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+
+    /* Read before curses starts, so a bad pattern is reported */
+    let ignored = read_patterns(IGNORE)?;
+
+    match &opt.command {
+        Some(Command::Stats) => {
+            let state = load_state("", &opt);
+            println!("{}", io::stats(&state));
+            return Ok(());
+        }
+        Some(Command::Import { input }) => {
+            let mut state = load_state("", &opt);
+            state.read_only = opt.read_only;
+            let imported = state.import_favorites(io::read_lines(input)?);
+            if imported > 0 {
+                write_favorites(&state)?;
+            }
+            println!("Imported {} favorites", imported);
+            return Ok(());
+        }
+        Some(Command::Export {
+            output,
+            view,
+            query,
+            shebang,
+        }) => {
+            let mut state = load_state(query.as_deref().unwrap_or_default(), &opt);
+            prepare_history(&mut state, &opt, &ignored);
+            set_search_mode(&mut state, &opt);
+            state.view = *view;
+            state.search();
+            let shebang = if *shebang {
+                Some(state.shell.as_str())
+            } else {
+                None
+            };
+            io::export(output, &state, shebang)?;
+            return Ok(());
+        }
+        Some(Command::Search { query, json }) => {
+            let mut state = load_state(&query.join(" "), &opt);
+            prepare_history(&mut state, &opt, &ignored);
+            set_search_mode(&mut state, &opt);
+            let started = Instant::now();
+            state.search();
+            state.timings.push(("search", started.elapsed()));
+            if *json {
+                println!("{}", io::matches_as_json(&state));
+            } else {
+                state
+                    .commands(state.view)
+                    .iter()
+                    .for_each(|cmd| println!("{}", cmd));
+            }
+            if opt.profile {
+                eprintln!("{}", io::profile(&state.timings));
+            }
+            return Ok(());
+        }
+        Some(Command::Delete { pattern, .. }) => {
+            let mut state = load_state(&pattern.join(" "), &opt);
+            prepare_history(&mut state, &opt, &ignored);
+            set_search_mode(&mut state, &opt);
+            state.read_only = opt.read_only;
+            let deleted = match delete_entries(&mut state) {
+                Ok(deleted) => deleted,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            if deleted > 0 {
+                write_history(&state, &opt, &mut false)?;
+            }
+            println!("Deleted {} history entries", deleted);
+            return Ok(());
+        }
+        Some(Command::Config { .. }) | None => {}
+    }
+
     let query = opt.query.join(" ");
-    let mut state = state::State::new(&query);
+    let redacted = read_patterns(REDACT)?;
+    let settings = if opt.no_persist {
+        None
+    } else {
+        Some(io::read_from_home(SETTINGS)?)
+    };
     let mut user_interface = ui::UserInterface::new(&query);
+    user_interface.show_help = !opt.hide_help;
+    user_interface.margin = opt.margin.max(0);
+    user_interface.max_rows = opt.max_rows;
+    user_interface.timestamps = opt.timestamps;
+    user_interface.status_format = opt.status_format.clone();
+    user_interface.prompt_format = opt.prompt_format.clone();
+    user_interface.navigation = opt.navigation;
+    user_interface.wrap = opt.wrap;
+    user_interface.enter_executes = !opt.no_execute;
+    user_interface.sticky = opt.sticky;
+    user_interface.redacted = redacted;
+    user_interface.mask = opt.mask;
+    if opt.vim {
+        user_interface.vim_mode = Some(if query.is_empty() {
+            ui::VimMode::Normal
+        } else {
+            ui::VimMode::Insert
+        });
+    }
 
-    ui::curses::init();
+    /* With --print, stdout is kept free for the command */
+    let session = ui::curses::Session::start(opt.print);
+    /* Sorting a huge history takes a while, so let the user know */
+    user_interface.notify_loading();
+    let mut state = load_state(&query, &opt);
+    state.read_only = opt.read_only;
+    if let Some(settings) = settings {
+        state.apply_settings(&settings);
+    }
+    if let Some(view) = opt.view {
+        state.start_in(view);
+    }
+    prepare_history(&mut state, &opt, &ignored);
+    set_search_mode(&mut state, &opt);
+    nc::clear();
+    let started = Instant::now();
     state.search();
-    state
-        .query
-        .clone()
-        .chars()
-        .for_each(|_| user_interface.move_cursor(&mut state, Direction::Forward));
+    state.timings.push(("first search", started.elapsed()));
+    user_interface.cursor_to_end(&mut state);
     user_interface.populate_screen(&state);
 
+    let mut printed = None;
+    let mut backed_up = false;
     loop {
-        let user_input = nc::get_wch();
-        match user_input.unwrap() {
+        if ui::curses::signalled().is_some() {
+            break;
+        }
+        /* Reading a key only fails on a signal or a lost terminal */
+        let user_input = match nc::get_wch() {
+            Some(user_input) => user_input,
+            None => break,
+        };
+        let normal_mode = user_interface.vim_mode == Some(ui::VimMode::Normal);
+        match user_input {
+            nc::WchResult::Char(CTRL_D) if normal_mode => {
+                user_interface.turn_page(&state, Direction::Forward);
+                user_interface.populate_screen(&state);
+            }
+            nc::WchResult::Char(CTRL_U) if normal_mode => {
+                user_interface.turn_page(&state, Direction::Backward);
+                user_interface.populate_screen(&state);
+            }
+            nc::WchResult::Char(ch)
+                if normal_mode
+                    && ui::is_vim_key(ch)
+                    && !quick_selects(opt.quick_select, &state, ch) =>
+            {
+                user_interface.vim_key(&state, ch);
+                user_interface.populate_screen(&state);
+            }
             nc::WchResult::Char(ch) => match ch {
                 CTRL_E => {
                     state.toggle_search_mode();
-                    user_interface.selected = 0;
+                    user_interface.select_first();
+                    state.rerun_search();
                     user_interface.populate_screen(&state);
                 }
                 CTRL_F => match user_interface.selected(&state) {
+                    Some(_) if !state.favorites_enabled => {
+                        user_interface.notify_favorites_disabled()
+                    }
+                    Some(_) if state.read_only => user_interface.notify_read_only(),
                     Some(command) => {
                         if state.view == View::Favorites {
                             user_interface.retain_selected(&state);
                         }
                         state.add_or_rm_fav(command);
-                        io::write_to_home(
-                            &format!(".config/hstr-rs/.{}_favorites", state.shell),
-                            state.commands(View::Favorites),
-                        )?;
+                        if state.pin_favorites {
+                            /* The command moves to or from the top */
+                            state.rerun_search();
+                        }
+                        user_interface.clamp_selection(&state);
+                        write_favorites(&state)?;
+                        nc::clear();
+                        user_interface.populate_screen(&state);
+                    }
+                    None => continue,
+                },
+                CTRL_V => match user_interface.selected(&state) {
+                    Some(_) if !state.favorites_enabled => {
+                        user_interface.notify_favorites_disabled()
+                    }
+                    Some(_) if state.read_only => user_interface.notify_read_only(),
+                    Some(command) => {
+                        let tags = state.tags_of(&command).join(" ");
+                        if let Some(tags) = user_interface.ask_for_tags(&command, &tags) {
+                            state.tag_favorite(command, &tags);
+                            write_favorites(&state)?;
+                            state.rerun_search();
+                            user_interface.clamp_selection(&state);
+                        }
                         nc::clear();
                         user_interface.populate_screen(&state);
                     }
                     None => continue,
                 },
+                TAB | ENTER if opt.sticky && user_interface.editing.is_some() => {
+                    let command = state.query.clone();
+                    user_interface.stop_editing(&mut state);
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                    copy(&command, &user_interface);
+                    user_interface.cursor_to_end(&mut state);
+                }
+                TAB | ENTER if user_interface.editing.is_some() => {
+                    let execute = ch == ENTER && user_interface.enter_executes;
+                    printed = paste(state.query.clone(), execute, &opt)?;
+                    break;
+                }
+                TAB | ENTER if opt.edit_before_paste => match user_interface.selected(&state) {
+                    Some(command) => {
+                        let command = state.assemble(&command, " && ");
+                        user_interface.start_editing(&mut state, &command);
+                        state.rerun_search();
+                        user_interface.populate_screen(&state);
+                        user_interface.cursor_to_end(&mut state);
+                    }
+                    None => continue,
+                },
+                TAB | ENTER if opt.sticky => match user_interface.selected(&state) {
+                    Some(command) => {
+                        copy(&state.assemble(&command, " && "), &user_interface);
+                        user_interface.cursor_to_end(&mut state);
+                    }
+                    None => continue,
+                },
                 TAB => match user_interface.selected(&state) {
                     Some(command) => {
-                        io::echo(command);
+                        printed = paste(state.assemble(&command, " && "), false, &opt)?;
                         break;
                     }
                     None => continue,
                 },
                 ENTER => match user_interface.selected(&state) {
                     Some(command) => {
-                        io::echo(command + "\n");
+                        let execute = user_interface.enter_executes;
+                        printed = paste(state.assemble(&command, " && "), execute, &opt)?;
                         break;
                     }
                     None => continue,
                 },
                 CTRL_T => {
                     state.toggle_case();
+                    user_interface.select_first();
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                }
+                CTRL_U => {
+                    state.remember_query();
+                    state.clear_query();
+                    user_interface.select_first();
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                    user_interface.cursor_to_end(&mut state);
+                }
+                CTRL_W => {
+                    state.remember_query();
+                    let cursor = state.delete_word(user_interface.cursor.chars_moved);
+                    user_interface.select_first();
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                    user_interface.cursor_to_start(&state);
+                    (0..cursor)
+                        .for_each(|_| user_interface.move_cursor(&mut state, Direction::Forward));
+                }
+                CTRL_X => {
+                    state.toggle_privileged_only();
+                    user_interface.select_first();
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                }
+                CTRL_L => {
+                    user_interface.toggle_wrap();
+                    user_interface.populate_screen(&state);
+                }
+                CTRL_R => {
+                    state.toggle_reversed();
+                    user_interface.select_first();
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                }
+                CTRL_BRACKET => {
+                    state.toggle_sort_mode();
+                    let reloaded = state.reload_history();
+                    state.search();
+                    user_interface.select_first();
+                    user_interface.populate_screen(&state);
+                    if reloaded.is_err() {
+                        user_interface.notify_favorites_unavailable();
+                    }
+                }
+                CTRL_B => {
+                    state.toggle_dedupe();
+                    user_interface.select_first();
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                }
+                CTRL_Y => {
+                    if !state.read_only && state.undo_deletion() {
+                        write_history(&state, &opt, &mut backed_up)?;
+                    }
+                    let reloaded = state.reload_history();
+                    state.search();
+                    nc::clear();
+                    user_interface.populate_screen(&state);
+                    if reloaded.is_err() {
+                        user_interface.notify_favorites_unavailable();
+                    }
+                }
+                ESC if user_interface.editing.is_some() => {
+                    user_interface.stop_editing(&mut state);
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                    user_interface.cursor_to_end(&mut state);
+                }
+                ESC if user_interface.vim_mode == Some(ui::VimMode::Insert) => {
+                    user_interface.vim_mode = Some(ui::VimMode::Normal);
                     user_interface.populate_screen(&state);
                 }
                 ESC => break,
                 CTRL_SLASH => {
-                    state.toggle_view();
-                    user_interface.selected = 0;
-                    user_interface.page = 1;
+                    user_interface.toggle_view(&mut state);
+                    if state.view == View::Favorites {
+                        /* Moving between tags narrows the favorites down */
+                        state.rerun_search();
+                        user_interface.clamp_selection(&state);
+                    }
+                    user_interface.populate_screen(&state);
+                }
+                CTRL_A => user_interface.cursor_to_start(&state),
+                CTRL_G => user_interface.cursor_to_end(&mut state),
+                CTRL_K => {
+                    let count = state.commands(state.view).len();
+                    if state.query.is_empty() || count == 0 {
+                        continue;
+                    }
+                    if state.read_only {
+                        user_interface.notify_read_only();
+                        continue;
+                    }
+                    user_interface.ask_before_batch_deletion(count);
+                    let mut reloaded = Ok(());
+                    if user_interface.confirm() {
+                        state.delete_matching();
+                        write_history(&state, &opt, &mut backed_up)?;
+                        reloaded = state.reload_history();
+                        state.search();
+                        user_interface.select_first();
+                    }
                     nc::clear();
                     user_interface.populate_screen(&state);
+                    if reloaded.is_err() {
+                        user_interface.notify_favorites_unavailable();
+                    }
+                }
+                CTRL_O => match user_interface.selected(&state) {
+                    Some(command) => {
+                        user_interface.preview(&state.assemble(&command, " && "));
+                        user_interface.populate_screen(&state);
+                    }
+                    None => continue,
+                },
+                CTRL_P => {
+                    state.previous_query();
+                    user_interface.select_first();
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                    user_interface.cursor_to_end(&mut state);
+                }
+                CTRL_N => {
+                    state.next_query();
+                    user_interface.select_first();
+                    state.rerun_search();
+                    user_interface.populate_screen(&state);
+                    user_interface.cursor_to_end(&mut state);
+                }
+                QUESTION_MARK if state.query.is_empty() => {
+                    /* Like F1, until something is typed */
+                    user_interface.help();
+                    user_interface.populate_screen(&state);
+                }
+                _ if quick_selects(opt.quick_select, &state, ch) => {
+                    user_interface.select_row(&state, ch as i32 - '1' as i32);
+                    user_interface.populate_screen(&state);
                 }
                 _ => {
-                    user_interface
-                        .insert_char_in_query(&mut state, std::char::from_u32(ch).unwrap());
+                    state.query_recall = None;
+                    let mut typed = std::char::from_u32(ch).unwrap().to_string();
+                    if !normal_mode {
+                        typed.push_str(&ui::curses::typed_ahead());
+                    }
+                    user_interface.insert_in_query(&mut state, &typed);
                     state.commands = state.to_restore.clone();
                     user_interface.cursor.query_char_widths = ui::get_char_widths(&state.query);
-                    user_interface.selected = 0;
-                    user_interface.page = 1;
-                    nc::clear();
+                    user_interface.select_first();
                     state.search();
                     user_interface.populate_screen(&state);
-                    user_interface.move_cursor(&mut state, Direction::Forward);
+                    typed
+                        .chars()
+                        .for_each(|_| user_interface.move_cursor(&mut state, Direction::Forward));
                 }
             },
             nc::WchResult::KeyCode(code) => match code {
+                nc::KEY_SLEFT => {
+                    user_interface.scroll_selected(&state, Direction::Backward);
+                    user_interface.populate_screen(&state);
+                }
+                nc::KEY_SRIGHT => {
+                    user_interface.scroll_selected(&state, Direction::Forward);
+                    user_interface.populate_screen(&state);
+                }
                 nc::KEY_LEFT => {
                     user_interface.move_cursor(&mut state, Direction::Backward);
                 }
@@ -129,6 +617,7 @@ fn main() -> Result<(), std::io::Error> {
                     user_interface.populate_screen(&state);
                 }
                 nc::KEY_BACKSPACE => {
+                    state.remember_query();
                     state.query = user_interface.remove_char_from_query(&state.query);
                     state.commands = state.to_restore.clone();
                     user_interface.cursor.query_char_widths = ui::get_char_widths(&state.query);
@@ -137,23 +626,43 @@ fn main() -> Result<(), std::io::Error> {
                     user_interface.populate_screen(&state);
                     user_interface.move_cursor(&mut state, Direction::Backward);
                 }
-                nc::KEY_DC => match user_interface.selected(&state) {
+                nc::KEY_DC | nc::KEY_SDC => match user_interface.selected(&state) {
+                    Some(_) if state.read_only => user_interface.notify_read_only(),
                     Some(command) => {
-                        user_interface.ask_before_deletion(&command);
-                        if nc::getch() == Y {
-                            user_interface.retain_selected(&state);
-                            state.delete_from_history(command);
-                            io::write_to_home(
-                                &format!(".{}_history", state.shell),
-                                &state.raw_history,
-                            )?;
+                        let all_occurrences = code == nc::KEY_DC;
+                        user_interface.ask_before_deletion(&command, all_occurrences);
+                        if user_interface.confirm() {
+                            if all_occurrences || state.occurrences(&command) == 1 {
+                                user_interface.retain_selected(&state);
+                            }
+                            if all_occurrences {
+                                state.delete_from_history(command);
+                            } else {
+                                let nth = user_interface
+                                    .selected_position(&state)
+                                    .and_then(|position| state.occurrence_at(position));
+                                state.delete_occurrence(command, nth);
+                            }
+                            write_history(&state, &opt, &mut backed_up)?;
                         }
-                        state.reload_history();
+                        let reloaded = state.reload_history();
+                        user_interface.clamp_selection(&state);
                         nc::clear();
                         user_interface.populate_screen(&state);
+                        if reloaded.is_err() {
+                            user_interface.notify_favorites_unavailable();
+                        }
                     }
                     None => continue,
                 },
+                nc::KEY_HOME => {
+                    user_interface.select_first();
+                    user_interface.populate_screen(&state);
+                }
+                nc::KEY_END => {
+                    user_interface.select_last(&state);
+                    user_interface.populate_screen(&state);
+                }
                 nc::KEY_NPAGE => {
                     user_interface.turn_page(&state, Direction::Forward);
                     user_interface.populate_screen(&state);
@@ -162,7 +671,73 @@ fn main() -> Result<(), std::io::Error> {
                     user_interface.turn_page(&state, Direction::Backward);
                     user_interface.populate_screen(&state);
                 }
+                nc::KEY_F2 => match user_interface.selected(&state) {
+                    Some(_) if !state.favorites_enabled => {
+                        user_interface.notify_favorites_disabled()
+                    }
+                    Some(_) if state.read_only => user_interface.notify_read_only(),
+                    Some(command) => {
+                        let note = state.note_of(&command).unwrap_or_default().to_string();
+                        if let Some(note) = user_interface.ask_for_note(&command, &note) {
+                            state.annotate(command, &note);
+                            write_favorites(&state)?;
+                            state.rerun_search();
+                            user_interface.clamp_selection(&state);
+                        }
+                        nc::clear();
+                        user_interface.populate_screen(&state);
+                    }
+                    None => continue,
+                },
+                nc::KEY_F3 => {
+                    /* The commands get narrower or wider, which moves wrapped pages */
+                    user_interface.toggle_timestamps();
+                    user_interface.clamp_selection(&state);
+                    nc::clear();
+                    user_interface.populate_screen(&state);
+                }
+                nc::KEY_F4 => {
+                    let range = state.length_range().unwrap_or_default();
+                    if let Some(range) = user_interface.ask_for_length_range(&range) {
+                        if state.set_length_range(&range) {
+                            user_interface.select_first();
+                            state.rerun_search();
+                        } else {
+                            user_interface.populate_screen(&state);
+                            user_interface.notify_invalid_length_range();
+                            continue;
+                        }
+                    }
+                    nc::clear();
+                    user_interface.populate_screen(&state);
+                }
+                nc::KEY_F5 => match user_interface.selected(&state) {
+                    Some(command) => {
+                        /* The editor takes the terminal over until it quits */
+                        nc::endwin();
+                        let edited = io::edit_in_editor(&state.assemble(&command, " && "));
+                        nc::refresh();
+                        nc::clear();
+                        user_interface.populate_screen(&state);
+                        match edited {
+                            Ok(Some(edited)) if opt.sticky => copy(&edited, &user_interface),
+                            Ok(Some(edited)) => {
+                                printed = paste(edited, false, &opt)?;
+                                break;
+                            }
+                            Ok(None) => {}
+                            Err(e) => user_interface.notify_editor_failed(&e),
+                        }
+                        user_interface.cursor_to_end(&mut state);
+                    }
+                    None => continue,
+                },
+                nc::KEY_F1 => {
+                    user_interface.help();
+                    user_interface.populate_screen(&state);
+                }
                 nc::KEY_RESIZE => {
+                    user_interface.clamp_selection(&state);
                     nc::clear();
                     user_interface.populate_screen(&state);
                 }
@@ -171,7 +746,429 @@ fn main() -> Result<(), std::io::Error> {
         }
     }
 
-    ui::curses::teardown();
+    drop(session);
+    ui::curses::exit_if_signalled();
+
+    if opt.profile {
+        eprintln!("{}", io::profile(&state.timings));
+    }
+
+    if let Some(command) = printed {
+        println!("{}", command);
+    }
+
+    if !opt.no_persist && !state.read_only {
+        io::write_to_home(SETTINGS, &state.settings())?;
+    }
 
     Ok(())
 }
+
+fn parse_args(args: Vec<String>) -> Opt {
+    /* The words after hstr-rs are a query, unless the first one is
+     * exactly the name of a subcommand and the rest parses as its
+     * arguments. So `hh config` and `hh stat` search rather than
+     * fail, and a subcommand name later on is part of the query.
+     * Files are only named with --input or --output, and deleting
+     * takes --yes, so `hh export PATH` and `hh delete branch` search.
+     * `help` is no subcommand either, that's what --help is for.
+     */
+    let first = args.get(1).map(String::as_str);
+    let subcommand = matches!(first, Some(arg) if SUBCOMMANDS.contains(&arg));
+    let app = || Opt::clap().setting(AppSettings::DisableHelpSubcommand);
+    if subcommand {
+        match app().get_matches_from_safe(&args) {
+            Ok(matches) => {
+                let opt = Opt::from_clap(&matches);
+                if !matches!(opt.command, Some(Command::Delete { yes: false, .. })) {
+                    return opt;
+                }
+            }
+            Err(e) if e.kind == clap::ErrorKind::HelpDisplayed => e.exit(),
+            Err(e) if e.kind == clap::ErrorKind::VersionDisplayed => e.exit(),
+            Err(_) => {}
+        }
+        let line = [&args[..1], &[String::from("--")], &args[1..]].concat();
+        return parse_args(line);
+    }
+    let mut app = app();
+    if first == Some("--") {
+        /* The shell bindings run `hstr-rs -- <line>`, but clap would refuse
+         * a line that looks like a subcommand, such as `stats`, unless
+         * subcommands are inferred, which does nothing after `--`
+         */
+        app = app.setting(AppSettings::InferSubcommands);
+    } else {
+        /* Once the query has started, no subcommand is looked for, and
+         * external subcommands turn off the suggestions for misspelt
+         * ones; they are never reached, the query takes every word
+         */
+        app = app
+            .setting(AppSettings::ArgsNegateSubcommands)
+            .setting(AppSettings::AllowExternalSubcommands);
+    }
+    Opt::from_clap(&app.get_matches_from(args))
+}
+
+fn set_search_mode(state: &mut state::State, opt: &Opt) {
+    if opt.regex {
+        state.search_mode = SearchMode::Regex;
+    } else if opt.fuzzy {
+        state.search_mode = SearchMode::Fuzzy;
+    } else if opt.loose {
+        state.search_mode = SearchMode::Loose;
+    }
+    state.smart_case = opt.smart_case;
+    state.pin_favorites = opt.pin_favorites;
+    state.min_length = opt.min_length;
+    state.max_length = opt.max_length;
+}
+
+fn paste(command: String, execute: bool, opt: &Opt) -> Result<Option<String>, Error> {
+    /* With --print, the command is handed back to be
+     * printed once curses has let go of the terminal.
+     * The newline that runs the command must stay outside the paste.
+     */
+    if opt.print {
+        return Ok(Some(command));
+    }
+    let backend = paste_backend(opt, env::var_os("TMUX").is_some());
+    if backend == io::PasteBackend::Clipboard {
+        /* Nothing to run from the clipboard */
+        io::paste_with(backend, &command)?;
+        return Ok(None);
+    }
+    let command = if opt.bracketed_paste {
+        io::bracketed(&command)
+    } else {
+        command
+    };
+    let newline = if execute { "\n" } else { "" };
+    io::paste_with(backend, &(command + newline))?;
+    Ok(None)
+}
+
+fn copy(command: &str, user_interface: &ui::UserInterface) {
+    /* With --sticky, hstr stays open, so a failure is shown rather than quitting */
+    match io::paste_with(io::PasteBackend::Clipboard, command) {
+        Ok(()) => user_interface.notify_copied(),
+        Err(e) => user_interface.notify_copy_failed(&e),
+    }
+}
+
+fn paste_backend(opt: &Opt, in_tmux: bool) -> io::PasteBackend {
+    /* Inside tmux, the keys go to the pane rather than to fd 0 */
+    match opt.paste_with {
+        Some(backend) => backend,
+        None if in_tmux => io::PasteBackend::Tmux,
+        None => io::PasteBackend::Tiocsti,
+    }
+}
+
+fn delete_entries(state: &mut state::State) -> Result<usize, Error> {
+    /* Unlike in the list, nothing shows what would be deleted,
+     * so an empty pattern, matching the whole history, is refused
+     */
+    if state.query.trim().is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Refusing to delete without a pattern",
+        ));
+    }
+    if state.read_only {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "History is read-only",
+        ));
+    }
+    state.view = View::All;
+    state.search();
+    let entries = state.raw_history.len();
+    state.delete_matching();
+    Ok(entries - state.raw_history.len())
+}
+
+fn write_history(state: &state::State, opt: &Opt, backed_up: &mut bool) -> Result<(), Error> {
+    /* The first rewrite of the session backs the history up */
+    if !*backed_up {
+        io::back_up(&state.history_file, opt.backup_dir.as_deref(), sort::now())?;
+        *backed_up = true;
+    }
+    io::write_to_home(&state.history_file, &state.history_lines())
+}
+
+fn write_favorites(state: &state::State) -> Result<(), Error> {
+    io::write_to_home(
+        state::favorites_path(&state.shell),
+        &state.to_restore.favorites,
+    )?;
+    io::write_to_home(
+        state::tags_path(&state.shell),
+        &favorites::tag_lines(&state.tags),
+    )?;
+    io::write_to_home(
+        state::notes_path(&state.shell),
+        &favorites::note_lines(&state.notes),
+    )
+}
+
+fn read_patterns(path: &str) -> Result<Vec<Regex>, Error> {
+    state::parse_patterns(&io::read_from_home(path)?)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path, e)))
+}
+
+fn load_state(query: &str, opt: &Opt) -> state::State {
+    state::State::load(
+        query,
+        opt.shell.as_deref(),
+        history_file(opt),
+        !opt.no_favorites,
+    )
+}
+
+fn history_file(opt: &Opt) -> Option<PathBuf> {
+    /* Paths are taken relative to the home directory further on,
+     * but one typed on the command line is relative to where it was typed
+     */
+    let cwd = env::current_dir().unwrap_or_default();
+    opt.history_file.as_ref().map(|path| cwd.join(path))
+}
+
+fn prepare_history(state: &mut state::State, opt: &Opt, ignored: &[Regex]) {
+    /* HISTCONTROL is only seen here if it is exported */
+    let histcontrol = env::var("HISTCONTROL").unwrap_or_default();
+    state.ignore_space = opt.ignore_space || hstr::bash::ignores_space(&histcontrol);
+    if let Some(sort_mode) = opt.sort {
+        state.sort_mode = sort_mode;
+    }
+    state.ignored = ignored.to_vec();
+    if state.shell == "bash" {
+        /* Like HISTCONTROL, HISTIGNORE has to be exported */
+        let histignore = env::var("HISTIGNORE").unwrap_or_default();
+        state
+            .ignored
+            .extend(hstr::bash::histignore_patterns(&histignore));
+    }
+    if state.ignore_space || !state.ignored.is_empty() || state.sort_mode != SortMode::Frequency {
+        /* load() has just read the favorites, so if reading
+         * them again fails, keeping those is all there is to do
+         */
+        let started = Instant::now();
+        let _ = state.reload_history();
+        state.timings.push(("resort", started.elapsed()));
+    }
+}
+
+fn quick_selects(quick_select: bool, state: &state::State, ch: u32) -> bool {
+    /* Digits 1-9 select a row on the page, until something is typed */
+    quick_select
+        && state.query.is_empty()
+        && ('1'..='9').contains(&std::char::from_u32(ch).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{io, state, Command, Opt, SearchMode};
+    use rstest::rstest;
+    use structopt::StructOpt;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.into()).collect()
+    }
+
+    #[rstest(
+        args,
+        case(vec!["hstr-rs"]),
+        case(vec!["hstr-rs", "--", "git", "push"]),
+        case(vec!["hstr-rs", "search", "git", "--json"]),
+        case(vec!["hstr-rs", "config", "zsh"]),
+        case(vec!["hstr-rs", "stats", "--shell", "bash"]),
+        case(vec!["hstr-rs", "import", "--input", "-"]),
+        case(vec!["hstr-rs", "delete", "--yes", "--regex", "^rm -rf"]),
+        case(vec!["hstr-rs", "export", "--output", "out.sh", "--view", "all", "--shebang"]),
+        case(vec!["hstr-rs", "--view", "favorites", "git"]),
+        case(vec!["hstr-rs", "--sticky", "--edit-before-paste"])
+    )]
+    fn parse_args(args: Vec<&str>) {
+        assert!(Opt::from_iter_safe(args).is_ok());
+    }
+
+    #[rstest(
+        quick_select,
+        query,
+        ch,
+        expected,
+        case(true, "", '1', true),
+        case(true, "", '9', true),
+        case(true, "", '0', false),
+        case(true, "", 'j', false),
+        case(true, "git", '1', false),
+        case(false, "", '1', false)
+    )]
+    fn quick_selects(quick_select: bool, query: &str, ch: char, expected: bool) {
+        let state = state::State::from_commands(
+            "bash",
+            query,
+            Vec::new(),
+            state::Commands::new(&[], Vec::new()),
+        );
+        assert_eq!(
+            super::quick_selects(quick_select, &state, ch as u32),
+            expected
+        );
+    }
+
+    #[rstest(
+        args,
+        expected,
+        case(vec!["hstr-rs"], None),
+        case(vec!["hstr-rs", "--history-file", "/tmp/history"], Some("/tmp/history")),
+        case(vec!["hstr-rs", "--history-file", "history"], Some("history")),
+        case(vec!["hstr-rs", "--history-file", "../history"], Some("../history"))
+    )]
+    fn history_file(args: Vec<&str>, expected: Option<&str>) {
+        let cwd = std::env::current_dir().unwrap();
+        let opt = Opt::from_iter_safe(args).unwrap();
+        assert_eq!(
+            super::history_file(&opt),
+            expected.map(|path| cwd.join(path))
+        );
+    }
+
+    #[test]
+    fn invalid_view() {
+        let error = Opt::from_iter_safe(vec!["hstr-rs", "--view", "spam"]).unwrap_err();
+        assert!(error.message.contains("possible values"));
+        assert!(state::VIEWS.iter().all(|view| error.message.contains(view)));
+    }
+
+    #[test]
+    fn sticky_with_print() {
+        assert!(Opt::from_iter_safe(vec!["hstr-rs", "--sticky", "--print"]).is_err());
+    }
+
+    #[rstest(
+        line,
+        expected,
+        case(&["git", "push"], vec!["git", "push"]),
+        case(&["--", "git", "push"], vec!["git", "push"]),
+        case(&["--", "stats"], vec!["stats"]),
+        case(&["--", "stat"], vec!["stat"]),
+        case(&["--", "search", "--json"], vec!["search", "--json"]),
+        case(&["stat"], vec!["stat"]),
+        case(&["config"], vec!["config"]),
+        case(&["import"], vec!["import"]),
+        case(&["git", "stats"], vec!["git", "stats"]),
+        case(&["echo", "export", "PATH"], vec!["echo", "export", "PATH"]),
+        case(&["--regex", "stat"], vec!["stat"]),
+        case(&["stat", "--regex"], vec!["stat"]),
+        case(&["export", "PATH"], vec!["export", "PATH"]),
+        case(&["export", "notes.txt", "--shebang"], vec!["export", "notes.txt", "--shebang"]),
+        case(&["import", "requirements.txt"], vec!["import", "requirements.txt"]),
+        case(&["help"], vec!["help"]),
+        case(&["help", "me"], vec!["help", "me"])
+    )]
+    fn bare_query(line: &[&str], expected: Vec<&str>) {
+        let opt = super::parse_args(args(&[&["hstr-rs"], line].concat()));
+        assert!(opt.command.is_none());
+        assert_eq!(opt.query, expected);
+    }
+
+    #[test]
+    fn subcommand() {
+        /* Only a first word that names a subcommand exactly runs it */
+        let opt = super::parse_args(args(&["hstr-rs", "export", "--output", "PATH"]));
+        assert!(matches!(opt.command, Some(Command::Export { .. })));
+        assert!(opt.query.is_empty());
+        let opt = super::parse_args(args(&["hstr-rs", "import", "--input", "-"]));
+        assert!(matches!(opt.command, Some(Command::Import { .. })));
+        let opt = super::parse_args(args(&["hstr-rs", "search", "foo", "--regex"]));
+        assert!(matches!(opt.command, Some(Command::Search { .. })));
+        assert!(opt.regex);
+    }
+
+    #[rstest(
+        line,
+        deletes,
+        case(&["delete", "foo"], false),
+        case(&["delete", "--regex", "^foo"], false),
+        case(&["delete", "foo", "--yes"], true),
+        case(&["delete", "--yes", "--regex", "^foo"], true)
+    )]
+    fn delete_needs_yes(line: &[&str], deletes: bool) {
+        /* Without --yes, the words are a query and nothing is deleted */
+        let opt = super::parse_args(args(&[&["hstr-rs"], line].concat()));
+        assert_eq!(matches!(opt.command, Some(Command::Delete { .. })), deletes);
+        if !deletes {
+            assert_eq!(opt.query[0], "delete");
+        }
+    }
+
+    #[rstest(
+        query,
+        regex,
+        read_only,
+        expected,
+        expected_history,
+        case("rm", false, false, Some(3), vec!["ls", "cd"]),
+        case("^rm -rf", true, false, Some(2), vec!["ls", "rm b", "cd"]),
+        case("spam", false, false, Some(0), vec!["rm -rf a", "ls", "rm -rf a", "rm b", "cd"]),
+        case("rm", false, true, None, vec!["rm -rf a", "ls", "rm -rf a", "rm b", "cd"]),
+        case(" ", false, false, None, vec!["rm -rf a", "ls", "rm -rf a", "rm b", "cd"])
+    )]
+    fn delete_entries(
+        query: &str,
+        regex: bool,
+        read_only: bool,
+        expected: Option<usize>,
+        expected_history: Vec<&str>,
+    ) {
+        let history = args(&["rm -rf a", "ls", "rm -rf a", "rm b", "cd"]);
+        let commands = state::Commands::new(&history, Vec::new());
+        let mut state = state::State::from_commands("bash", query, history, commands);
+        state.read_only = read_only;
+        if regex {
+            state.search_mode = SearchMode::Regex;
+        }
+        assert_eq!(super::delete_entries(&mut state).ok(), expected);
+        assert_eq!(state.raw_history, expected_history);
+    }
+
+    #[rstest(execute, case(true), case(false))]
+    fn paste_with_print(execute: bool) {
+        /* Printed as it is, without the paste markers or a newline */
+        let opt = Opt::from_iter(vec!["hstr-rs", "--print", "--bracketed-paste"]);
+        assert_eq!(
+            super::paste(String::from("ls -la"), execute, &opt)
+                .unwrap()
+                .as_deref(),
+            Some("ls -la")
+        );
+    }
+
+    #[rstest(
+        flag,
+        in_tmux,
+        expected,
+        case(None, false, io::PasteBackend::Tiocsti),
+        case(None, true, io::PasteBackend::Tmux),
+        case(Some("tiocsti"), true, io::PasteBackend::Tiocsti),
+        case(Some("clipboard"), false, io::PasteBackend::Clipboard)
+    )]
+    fn paste_backend(flag: Option<&str>, in_tmux: bool, expected: io::PasteBackend) {
+        let mut line = vec!["hstr-rs"];
+        if let Some(backend) = flag {
+            line.extend(&["--paste-with", backend]);
+        }
+        let opt = Opt::from_iter(line);
+        assert_eq!(super::paste_backend(&opt, in_tmux), expected);
+    }
+
+    #[test]
+    fn global_args_after_subcommand() {
+        let opt = Opt::from_iter(vec!["hstr-rs", "search", "ls", "--fuzzy", "--shell", "zsh"]);
+        assert!(matches!(opt.command, Some(Command::Search { .. })));
+        assert!(opt.fuzzy);
+        assert_eq!(opt.shell.as_deref(), Some("zsh"));
+    }
+}
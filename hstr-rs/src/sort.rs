@@ -1,21 +1,162 @@
+use itertools::Itertools;
 use std::{
-    cmp::{Eq, Reverse},
+    cmp::{Eq, Ordering, Reverse},
     collections::HashMap,
     hash::Hash,
+    time::{SystemTime, UNIX_EPOCH},
 };
+use strum_macros::{AsRefStr, EnumString};
 
-pub fn sort<T>(mut commands: Vec<T>) -> Vec<T>
+#[derive(AsRefStr, Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum SortMode {
+    Frequency = 0,
+    Recency = 1,
+    Frecency = 2,
+    Alphabetical = 3,
+    Length = 4,
+}
+
+pub fn ordered(sort_mode: SortMode, history: &[(String, Option<u64>)], now: u64) -> Vec<String> {
+    /* Every strategy returns each command once */
+    let commands = chronological(history);
+    match sort_mode {
+        SortMode::Frequency => sort(commands),
+        SortMode::Recency => recency(commands),
+        SortMode::Frecency => frecency(history, now),
+        SortMode::Alphabetical => alphabetical(commands),
+        SortMode::Length => length(commands),
+    }
+}
+
+pub fn chronological(history: &[(String, Option<u64>)]) -> Vec<String> {
+    /* Sessions appending to the same history file can interleave,
+     * so the file is not always in the order the commands were run.
+     * An entry without a timestamp stays right after the one before it.
+     */
+    let mut last = 0;
+    history
+        .iter()
+        .map(|(cmd, timestamp)| {
+            last = timestamp.unwrap_or(last);
+            (last, cmd)
+        })
+        .collect::<Vec<(u64, &String)>>()
+        .into_iter()
+        .sorted_by_key(|(timestamp, _)| *timestamp)
+        .map(|(_, cmd)| cmd.clone())
+        .collect()
+}
+
+pub fn is_chronological(history: &[(String, Option<u64>)]) -> bool {
+    history
+        .iter()
+        .filter_map(|(_, timestamp)| *timestamp)
+        .tuple_windows()
+        .all(|(earlier, later)| earlier <= later)
+}
+
+pub fn sort<T>(commands: Vec<T>) -> Vec<T>
 where
     T: Clone + Eq + Hash,
 {
+    /* Most frequent first, ties go to the most recent */
     let freq_map = frequency_map(&commands);
+    let mut commands = recency(commands);
+    commands.sort_by_key(|c| Reverse(freq_map[c]));
+    commands
+}
+
+pub fn recency<T>(mut commands: Vec<T>) -> Vec<T>
+where
+    T: Clone + Eq + Hash,
+{
+    /* The history is in the order the commands were run,
+     * so the last position of a command is its latest run
+     */
     let pos_map = position_map(&commands);
-    commands.sort_by_key(|c| Reverse(pos_map.get(c).unwrap()));
+    commands.sort_by_key(|c| Reverse(pos_map[c]));
     commands.dedup();
-    commands.sort_by_key(|c| Reverse(freq_map.get(c).unwrap()));
     commands
 }
 
+pub fn alphabetical(commands: Vec<String>) -> Vec<String> {
+    commands
+        .into_iter()
+        .unique()
+        .sorted_by(|a, b| {
+            a.to_lowercase()
+                .cmp(&b.to_lowercase())
+                .then_with(|| a.cmp(b))
+        })
+        .collect()
+}
+
+pub fn length(commands: Vec<String>) -> Vec<String> {
+    /* Shortest first, ties go to the most frequent */
+    let mut commands = sort(commands);
+    commands.sort_by_key(|c| c.chars().count());
+    commands
+}
+
+pub fn most_frequent(commands: &[String], n: usize) -> Vec<(String, usize)> {
+    let freq_map = frequency_map(commands);
+    sort(commands.to_vec())
+        .into_iter()
+        .take(n)
+        .map(|cmd| {
+            let count = freq_map[&cmd];
+            (cmd, count)
+        })
+        .collect()
+}
+
+pub fn frecency(history: &[(String, Option<u64>)], now: u64) -> Vec<String> {
+    let scores = frecency_scores(history, now);
+    let mut unique = recency(chronological(history));
+    unique.sort_by(|a, b| scores[b].partial_cmp(&scores[a]).unwrap_or(Ordering::Equal));
+    unique
+}
+
+pub fn frecency_scores(history: &[(String, Option<u64>)], now: u64) -> HashMap<String, f64> {
+    /* Like z, every run of a command adds to its score,
+     * with recent runs weighing more than the old ones:
+     *
+     * Within the last hour: 4
+     * Within the last day: 2
+     * Within the last week: 0.5
+     * Older than that: 0.25
+     *
+     * Runs without a timestamp weigh 1, so with no
+     * timestamps at all the score is the frequency.
+     */
+    let mut map = HashMap::new();
+    history.iter().for_each(|(cmd, timestamp)| {
+        *map.entry(cmd.to_owned()).or_insert(0.0) += weight(*timestamp, now);
+    });
+    map
+}
+
+fn weight(timestamp: Option<u64>, now: u64) -> f64 {
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+    const WEEK: u64 = 7 * DAY;
+    match timestamp.map(|ts| now.saturating_sub(ts)) {
+        None => 1.0,
+        Some(age) if age < HOUR => 4.0,
+        Some(age) if age < DAY => 2.0,
+        Some(age) if age < WEEK => 0.5,
+        Some(_) => 0.25,
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
 fn frequency_map<T>(commands: &[T]) -> HashMap<T, usize>
 where
     T: Clone + Eq + Hash,
@@ -40,10 +181,172 @@ where
 
 #[cfg(test)]
 mod tests {
+    use super::SortMode;
+    use rstest::rstest;
+
+    const NOW: u64 = 1_600_000_000;
+
+    fn timed(history: &[(&str, Option<u64>)]) -> Vec<(String, Option<u64>)> {
+        history
+            .iter()
+            .map(|&(cmd, ts)| (cmd.to_string(), ts))
+            .collect()
+    }
+
     #[test]
     fn sort() {
         let vec = vec![3, 2, 4, 6, 2, 4, 3, 3, 4, 5, 6, 3, 2, 4, 5, 5, 3];
         let sorted_vec = super::sort(vec);
         assert_eq!(sorted_vec, [3, 4, 5, 2, 6]);
     }
+
+    #[test]
+    fn most_frequent() {
+        let commands: Vec<String> = ["ls", "cd", "ls", "make", "cd", "ls"]
+            .iter()
+            .map(|cmd| cmd.to_string())
+            .collect();
+        assert_eq!(
+            super::most_frequent(&commands, 2),
+            [(String::from("ls"), 3), (String::from("cd"), 2)]
+        );
+        assert_eq!(super::most_frequent(&commands, 10).len(), 3);
+    }
+
+    #[rstest(
+        timestamp,
+        expected,
+        case(None, 1.0),
+        case(Some(NOW - 60), 4.0),
+        case(Some(NOW - 2 * 60 * 60), 2.0),
+        case(Some(NOW - 2 * 24 * 60 * 60), 0.5),
+        case(Some(NOW - 30 * 24 * 60 * 60), 0.25),
+        case(Some(NOW + 60), 4.0)
+    )]
+    fn weight(timestamp: Option<u64>, expected: f64) {
+        assert_eq!(super::weight(timestamp, NOW), expected);
+    }
+
+    #[test]
+    fn frecency_scores() {
+        let history = timed(&[
+            ("ls", Some(NOW - 60)),
+            ("make", Some(NOW - 30 * 24 * 60 * 60)),
+            ("ls", Some(NOW - 2 * 60 * 60)),
+        ]);
+        let scores = super::frecency_scores(&history, NOW);
+        assert_eq!(scores["ls"], 6.0);
+        assert_eq!(scores["make"], 0.25);
+    }
+
+    #[test]
+    fn frecency() {
+        let month = 30 * 24 * 60 * 60;
+        let history = timed(&[
+            ("make", Some(NOW - month)),
+            ("make", Some(NOW - month)),
+            ("make", Some(NOW - month)),
+            ("cargo test", Some(NOW - 60)),
+            ("ls", Some(NOW - month)),
+        ]);
+        assert_eq!(
+            super::frecency(&history, NOW),
+            vec!["cargo test", "make", "ls"]
+        );
+    }
+
+    #[test]
+    fn frecency_without_timestamps() {
+        let commands = ["ls", "make", "ls", "pwd", "make", "cd"];
+        let history = timed(&commands.iter().map(|&cmd| (cmd, None)).collect::<Vec<_>>());
+        assert_eq!(
+            super::frecency(&history, NOW),
+            super::sort(commands.iter().map(|&x| x.to_string()).collect())
+        );
+    }
+
+    fn history(commands: &[&str]) -> Vec<String> {
+        commands.iter().map(|&cmd| cmd.to_string()).collect()
+    }
+
+    #[test]
+    fn recency() {
+        let commands = history(&["ls", "make", "ls", "pwd", "make", "cd"]);
+        assert_eq!(super::recency(commands), ["cd", "make", "pwd", "ls"]);
+    }
+
+    #[rstest(
+        history,
+        expected,
+        in_order,
+        case(vec![("ls", None), ("cd", None)], vec!["ls", "cd"], true),
+        case(
+            vec![("ls", Some(5)), ("cd", Some(1)), ("pwd", Some(3))],
+            vec!["cd", "pwd", "ls"],
+            false
+        ),
+        case(
+            vec![("ls", None), ("cd", Some(5)), ("pwd", None), ("make", Some(1))],
+            vec!["ls", "make", "cd", "pwd"],
+            false
+        ),
+        case(vec![("ls", Some(1)), ("cd", None), ("pwd", Some(3))], vec!["ls", "cd", "pwd"], true)
+    )]
+    fn chronological(history: Vec<(&str, Option<u64>)>, expected: Vec<&str>, in_order: bool) {
+        let history = timed(&history);
+        assert_eq!(super::chronological(&history), expected);
+        assert_eq!(super::is_chronological(&history), in_order);
+    }
+
+    #[test]
+    fn alphabetical() {
+        let commands = history(&["make", "ls", "Makefile", "cd", "ls", "LS"]);
+        assert_eq!(
+            super::alphabetical(commands),
+            ["cd", "LS", "ls", "make", "Makefile"]
+        );
+    }
+
+    #[test]
+    fn length() {
+        let commands = history(&["make", "ls", "cd /", "cd", "cd", "make install"]);
+        assert_eq!(
+            super::length(commands),
+            ["cd", "ls", "cd /", "make", "make install"]
+        );
+    }
+
+    #[rstest(
+        sort_mode,
+        expected,
+        case(SortMode::Frequency, vec!["ls", "make", "pwd"]),
+        case(SortMode::Recency, vec!["pwd", "ls", "make"]),
+        case(SortMode::Frecency, vec!["ls", "pwd", "make"]),
+        case(SortMode::Alphabetical, vec!["ls", "make", "pwd"]),
+        case(SortMode::Length, vec!["ls", "pwd", "make"])
+    )]
+    fn ordered(sort_mode: SortMode, expected: Vec<&str>) {
+        let history = timed(&[
+            ("make", Some(NOW - 30 * 24 * 60 * 60)),
+            ("ls", Some(NOW - 2 * 60 * 60)),
+            ("make", Some(NOW - 30 * 24 * 60 * 60)),
+            ("ls", Some(NOW - 2 * 60 * 60)),
+            ("ls", Some(NOW - 2 * 60 * 60)),
+            ("pwd", Some(NOW - 60)),
+        ]);
+        assert_eq!(super::ordered(sort_mode, &history, NOW), expected);
+    }
+
+    #[rstest(
+        value,
+        expected,
+        case("frequency", SortMode::Frequency),
+        case("recency", SortMode::Recency),
+        case("frecency", SortMode::Frecency),
+        case("alphabetical", SortMode::Alphabetical),
+        case("length", SortMode::Length)
+    )]
+    fn parse_sort_mode(value: &str, expected: SortMode) {
+        assert_eq!(value.parse::<SortMode>().unwrap(), expected);
+    }
 }
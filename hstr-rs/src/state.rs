@@ -1,40 +1,176 @@
-use crate::{hstr, io, sort};
+use crate::favorites::{self, Notes, Tags};
+use crate::sort::{self, SortMode};
+use crate::{hstr, io};
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 use itertools::Itertools;
 use regex::{escape, Regex, RegexBuilder};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    env,
+    io::Error,
+    iter,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 use strum::IntoEnumIterator;
-use strum_macros::EnumIter;
+use strum_macros::{AsRefStr, EnumIter, EnumString};
+
+/* A deleted entry's position, command, timestamp and duration */
+pub type Deleted = (usize, String, Option<u64>, Option<u64>);
 
 #[derive(Clone)]
 pub struct State {
     pub case_sensitivity: bool,
+    pub smart_case: bool,
+    pub privileged_only: bool,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub dedupe: bool,
+    pub reversed: bool,
+    pub pin_favorites: bool,
+    pub invalid_regex: bool,
+    pub read_only: bool,
+    pub favorites_enabled: bool,
+    pub ignore_space: bool,
+    pub ignored: Vec<Regex>,
+    pub sort_mode: SortMode,
     pub search_mode: SearchMode,
     pub view: View,
     pub shell: String,
     pub query: String,
+    pub query_history: Vec<String>,
+    pub query_recall: Option<usize>,
+    pub cwd: PathBuf,
+    pub history_file: PathBuf,
     pub raw_history: Vec<String>,
+    pub timestamps: Vec<Option<u64>>,
+    /* How long each command ran, as zsh records it */
+    pub durations: Vec<Option<u64>>,
+    pub deleted: Vec<Vec<Deleted>>,
+    pub directory_history: Option<Vec<(String, String)>>,
     pub commands: Commands,
     pub to_restore: Commands,
+    /* Favorites by their words, kept in step with to_restore */
+    favorite_words: HashMap<String, String>,
+    pub tags: Tags,
+    pub notes: Notes,
+    pub tag: Option<String>,
+    pub search_regex: Option<(String, bool, Regex)>,
+    /* How long each stage of starting up took, for --profile */
+    pub timings: Vec<(&'static str, Duration)>,
 }
 
 impl State {
     pub fn new(query: &str) -> Self {
-        let shell = setenv::get_shell().get_name();
-        let (raw_history, commands) = match shell {
-            "bash" => hstr::get_bash_history(),
-            "zsh" => hstr::get_zsh_history(),
-            _ => panic!("{} is not supported yet.", shell),
+        Self::load(query, None, None, true)
+    }
+
+    pub fn load(
+        query: &str,
+        shell: Option<&str>,
+        history_file: Option<PathBuf>,
+        favorites: bool,
+    ) -> Self {
+        let shell = shell.unwrap_or_else(|| setenv::get_shell().get_name());
+        let history_file =
+            history_file.unwrap_or_else(|| hstr::history_path(shell, env::var("HISTFILE").ok()));
+        let started = Instant::now();
+        let timed = match hstr::history_source(shell, history_file.clone()) {
+            Some(source) => source.load_timed(),
+            None => panic!("{} is not supported yet.", shell),
         };
+        let mut timestamps = Vec::with_capacity(timed.len());
+        let mut durations = Vec::with_capacity(timed.len());
+        let mut raw_history = Vec::with_capacity(timed.len());
+        for (timestamp, duration, command) in timed {
+            timestamps.push(timestamp);
+            durations.push(duration);
+            raw_history.push(command);
+        }
+        let loaded = started.elapsed();
+        let started = Instant::now();
+        let mut commands = if favorites {
+            Commands::from_history(shell, &raw_history).unwrap()
+        } else {
+            Commands::new(&raw_history, Vec::new())
+        };
+        let timestamped = raw_history
+            .iter()
+            .cloned()
+            .zip(timestamps.iter().cloned())
+            .collect::<Vec<(String, Option<u64>)>>();
+        if !sort::is_chronological(&timestamped) {
+            /* Ties in frequency go to the latest run, not the latest line */
+            commands.resort(sort::ordered(
+                SortMode::Frequency,
+                &timestamped,
+                sort::now(),
+            ));
+        }
+        let mut state = Self::from_commands(shell, query, raw_history, commands);
+        state.timings = vec![("load history", loaded), ("sort", started.elapsed())];
+        if favorites {
+            state.tags = read_tags(shell).unwrap();
+            state.notes = read_notes(shell).unwrap();
+        }
+        state.favorites_enabled = favorites;
+        state.timestamps = timestamps;
+        state.durations = durations;
+        state.history_file = history_file;
+        state.directory_history = hstr::get_directory_history(shell);
+        state.commands.this_dir = this_dir(
+            &state.cwd,
+            &state.raw_history,
+            state.directory_history.as_deref(),
+        );
+        state.to_restore = state.commands.clone();
+        state
+    }
+
+    pub fn from_commands(
+        shell: &str,
+        query: &str,
+        raw_history: Vec<String>,
+        commands: Commands,
+    ) -> Self {
         Self {
             case_sensitivity: false,
+            smart_case: false,
+            privileged_only: false,
+            min_length: None,
+            max_length: None,
+            dedupe: false,
+            reversed: false,
+            pin_favorites: false,
+            invalid_regex: false,
+            read_only: false,
+            favorites_enabled: true,
+            ignore_space: false,
+            ignored: Vec::new(),
+            sort_mode: SortMode::Frequency,
             search_mode: SearchMode::Exact,
             view: View::Sorted,
             shell: shell.to_string(),
             query: query.to_owned(),
+            query_history: Vec::new(),
+            query_recall: None,
+            cwd: env::current_dir().unwrap_or_default(),
+            history_file: PathBuf::from(hstr::history_file(shell)),
+            timestamps: vec![None; raw_history.len()],
+            durations: vec![None; raw_history.len()],
             raw_history,
+            deleted: Vec::new(),
+            directory_history: None,
+            favorite_words: index_favorites(&commands.favorites),
             commands: commands.clone(),
             to_restore: commands,
+            tags: Tags::new(),
+            notes: Notes::new(),
+            tag: None,
+            search_regex: None,
+            timings: Vec::new(),
         }
     }
 
@@ -43,6 +179,9 @@ impl State {
             View::Sorted => &self.commands.sorted,
             View::Favorites => &self.commands.favorites,
             View::All => &self.commands.all,
+            View::ThisDir => &self.commands.this_dir,
+            View::Combined => &self.commands.combined,
+            View::Timeline => &self.commands.timeline,
         }
     }
 
@@ -51,131 +190,943 @@ impl State {
             View::Sorted => &mut self.commands.sorted,
             View::Favorites => &mut self.commands.favorites,
             View::All => &mut self.commands.all,
+            View::ThisDir => &mut self.commands.this_dir,
+            View::Combined => &mut self.commands.combined,
+            View::Timeline => &mut self.commands.timeline,
         }
     }
 
     pub fn search(&mut self) {
+        self.filter();
+        if self.dedupe {
+            let deduped = dedupe(self.commands(self.view));
+            *self.commands_mut(self.view) = deduped;
+        }
+        if self.reversed && self.view == View::Sorted {
+            self.commands_mut(View::Sorted).reverse();
+        }
+        if self.pin_favorites && self.view != View::Favorites {
+            self.float_favorites();
+        }
+    }
+
+    fn float_favorites(&mut self) {
+        /* Favorites go on top of the other views; the partition
+         * is stable, so both parts keep the order of the view
+         */
+        let commands = std::mem::take(self.commands_mut(self.view));
+        let (pinned, rest): (Vec<String>, Vec<String>) =
+            commands.into_iter().partition(|x| self.cmd_in_fav(x));
+        *self.commands_mut(self.view) = pinned.into_iter().chain(rest).collect();
+    }
+
+    fn filter(&mut self) {
+        if let (View::Favorites, Some(tag)) = (self.view, &self.tag) {
+            let tagged = self.tags.get(tag).cloned().unwrap_or_default();
+            self.commands.favorites.retain(|x| tagged.contains(x));
+        }
+        if self.privileged_only {
+            self.commands_mut(self.view).retain(|x| is_privileged(x));
+        }
+        if self.length_range().is_some() {
+            let (min, max) = (self.min_length, self.max_length);
+            self.commands_mut(self.view)
+                .retain(|x| fits_length(x, min, max));
+        }
+        self.invalid_regex = false;
+        /* The timeline keeps matches in the order they were run */
+        let ranked = self.view != View::Timeline;
+        /* Taken out of the view while filtering, so that a favorite
+         * can also match through its note without copying the notes
+         */
+        let mut commands = std::mem::take(self.commands_mut(self.view));
         match self.search_mode {
-            SearchMode::Exact | SearchMode::Regex => {
-                let search_regex = match self.create_search_regex() {
-                    Some(r) => r,
-                    None => {
-                        return;
+            SearchMode::Exact | SearchMode::Regex | SearchMode::Loose => {
+                match self.create_search_regex() {
+                    Some(search_regex) => {
+                        let noted = |x: &str| self.note_of(x).unwrap_or_default();
+                        commands.retain(|x| {
+                            search_regex.is_match(x) || search_regex.is_match(noted(x))
+                        });
+                        if ranked && self.search_mode != SearchMode::Regex {
+                            commands.sort_by_cached_key(|x| rank(x, &search_regex));
+                        }
                     }
-                };
-                self.commands_mut(self.view)
-                    .retain(|x| search_regex.is_match(x));
+                    None => self.invalid_regex = true,
+                }
             }
             SearchMode::Fuzzy => {
                 let query = self.query.clone();
-                if self.case_sensitivity {
-                    let matcher = SkimMatcherV2::default().respect_case();
-                    self.commands_mut(self.view)
-                        .retain(|x| matcher.fuzzy_match(x, query.as_str()).is_some());
+                let matcher = if self.is_case_sensitive() {
+                    SkimMatcherV2::default().respect_case()
                 } else {
-                    let matcher = SkimMatcherV2::default();
-                    self.commands_mut(self.view)
-                        .retain(|x| matcher.fuzzy_match(x, query.as_str()).is_some());
+                    SkimMatcherV2::default()
+                };
+                let noted = |x: &str| self.note_of(x).unwrap_or_default();
+                let score = |x: &str| {
+                    matcher
+                        .fuzzy_match(x, query.as_str())
+                        .max(matcher.fuzzy_match(noted(x), query.as_str()))
+                };
+                commands.retain(|x| score(x).is_some());
+                if ranked {
+                    /* Best matches first; equal scores go to the most recently
+                     * used, then alphabetically. Without timestamps, the stable
+                     * sort keeps them in the order of the view.
+                     */
+                    let last_used = self.last_used();
+                    commands.sort_by_cached_key(|x| {
+                        let timestamp = last_used.get(x.as_str()).copied();
+                        (
+                            Reverse(score(x)),
+                            Reverse(timestamp),
+                            timestamp.map(|_| x.clone()),
+                        )
+                    });
                 }
             }
         }
+        *self.commands_mut(self.view) = commands;
+    }
+
+    pub fn rerun_search(&mut self) {
+        self.commands = self.to_restore.clone();
+        self.search();
     }
 
-    fn create_search_regex(&self) -> Option<Regex> {
+    fn create_search_regex(&mut self) -> Option<Regex> {
         let query = match self.search_mode {
             SearchMode::Regex => self.query.clone(),
             SearchMode::Exact => escape(&self.query),
+            SearchMode::Loose => loose_pattern(&self.query),
             _ => unreachable!(),
         };
-        RegexBuilder::new(&query)
-            .case_insensitive(!self.case_sensitivity)
+        /* The escaped query already tells exact and regex mode apart */
+        let case_sensitive = self.is_case_sensitive();
+        if let Some((cached, case_sensitivity, regex)) = &self.search_regex {
+            if *cached == query && *case_sensitivity == case_sensitive {
+                return Some(regex.clone());
+            }
+        }
+        let regex = RegexBuilder::new(&query)
+            .case_insensitive(!case_sensitive)
             .build()
-            .ok()
+            .ok()?;
+        self.search_regex = Some((query, case_sensitive, regex.clone()));
+        Some(regex)
     }
 
     pub fn add_or_rm_fav(&mut self, command: String) {
-        let favorites = self.commands_mut(View::Favorites);
-        if !favorites.contains(&command) {
-            favorites.push(command);
+        if self.read_only || !self.favorites_enabled {
+            return;
+        }
+        /* A favorite that only differs in whitespace is the same
+         * favorite, so that one is removed rather than a twin added
+         */
+        match self.favorite_like(&command) {
+            None => self.both_commands().for_each(|commands| {
+                commands.favorites.push(command.clone());
+                if !commands.combined.contains(&command) {
+                    commands.combined.push(command.clone());
+                }
+            }),
+            Some(favorite) => {
+                self.untag(&favorite);
+                self.notes.remove(&favorite);
+                let in_history = self.raw_history.contains(&favorite);
+                self.both_commands().for_each(|commands| {
+                    commands.favorites.retain(|x| *x != favorite);
+                    if !in_history {
+                        commands.combined.retain(|x| *x != favorite);
+                    }
+                });
+            }
+        }
+        self.index_favorites();
+    }
+
+    fn both_commands(&mut self) -> impl Iterator<Item = &mut Commands> {
+        /* What is shown may be narrowed down by the query or a tag,
+         * so favorites change in the full lists too, which are the
+         * ones written to the favorites file
+         */
+        vec![&mut self.commands, &mut self.to_restore].into_iter()
+    }
+
+    pub fn tag_favorite(&mut self, command: String, tags: &str) {
+        /* Tags are separated by whitespace, and replace the ones the
+         * favorite had; no tags at all leave it untagged. The command
+         * becomes a favorite if it isn't one yet.
+         */
+        if self.read_only || !self.favorites_enabled {
+            return;
+        }
+        let favorite = match self.favorite_like(&command) {
+            Some(favorite) => favorite,
+            None => {
+                self.add_or_rm_fav(command.clone());
+                command
+            }
+        };
+        self.untag(&favorite);
+        tags.split_whitespace()
+            .filter(|tag| favorites::is_tag(tag))
+            .for_each(|tag| {
+                let tagged = self.tags.entry(tag.to_string()).or_default();
+                if !tagged.contains(&favorite) {
+                    tagged.push(favorite.clone());
+                }
+            });
+        if let Some(tag) = &self.tag {
+            if !self.tags.contains_key(tag) {
+                self.tag = None;
+            }
+        }
+    }
+
+    pub fn annotate(&mut self, command: String, note: &str) {
+        /* An empty note removes it; like tagging, noting a
+         * command makes it a favorite if it isn't one yet
+         */
+        if self.read_only || !self.favorites_enabled {
+            return;
+        }
+        let favorite = match self.favorite_like(&command) {
+            Some(favorite) => favorite,
+            None => {
+                self.add_or_rm_fav(command.clone());
+                command
+            }
+        };
+        let note = note.split_whitespace().join(" ");
+        if note.is_empty() {
+            self.notes.remove(&favorite);
         } else {
-            favorites.retain(|x| *x != command);
+            self.notes.insert(favorite, note);
+        }
+    }
+
+    pub fn note_of(&self, cmd: &str) -> Option<&str> {
+        if self.notes.is_empty() {
+            return None;
+        }
+        self.favorite_like(cmd)
+            .and_then(|favorite| self.notes.get(&favorite))
+            .map(String::as_str)
+    }
+
+    pub fn tags_of(&self, cmd: &str) -> Vec<&str> {
+        match self.favorite_like(cmd) {
+            Some(favorite) => favorites::tags_of(&self.tags, &favorite),
+            None => Vec::new(),
+        }
+    }
+
+    fn untag(&mut self, favorite: &str) {
+        self.tags
+            .values_mut()
+            .for_each(|tagged| tagged.retain(|cmd| cmd != favorite));
+        self.tags.retain(|_, tagged| !tagged.is_empty());
+    }
+
+    fn favorite_like(&self, cmd: &str) -> Option<String> {
+        /* Looked up for every row drawn, so not by going through all favorites */
+        self.favorite_words.get(&words(cmd)).cloned()
+    }
+
+    pub fn index_favorites(&mut self) {
+        self.favorite_words = index_favorites(&self.to_restore.favorites);
+    }
+
+    pub fn import_favorites(&mut self, commands: Vec<String>) -> usize {
+        if self.read_only || !self.favorites_enabled {
+            return 0;
         }
+        let mut imported = 0;
+        commands
+            .into_iter()
+            .filter_map(hstr::tidy)
+            .for_each(|command| {
+                if !self.cmd_in_fav(&command) {
+                    self.add_or_rm_fav(command);
+                    imported += 1;
+                }
+            });
+        imported
     }
 
     pub fn cmd_in_fav(&self, cmd: &str) -> bool {
-        self.commands.favorites.contains(&cmd.to_string())
+        self.favorite_like(cmd).is_some()
+    }
+
+    pub fn assemble(&self, cmd: &str, joiner: &str) -> String {
+        /* Only favorites are sequences; in history, `;;;` is just text */
+        if self.cmd_in_fav(cmd) {
+            favorites::assemble(cmd, joiner)
+        } else {
+            cmd.to_string()
+        }
     }
 
     pub fn delete_from_history(&mut self, command: String) {
+        self.delete_commands(&[command]);
+    }
+
+    pub fn delete_matching(&mut self) -> usize {
+        /* Deletes everything the current view shows, which is
+         * what matches the query. An empty query matches the
+         * whole view, so nothing is deleted then.
+         */
+        if self.query.is_empty() {
+            return 0;
+        }
+        let matching = self.commands(self.view).to_vec();
+        self.delete_commands(&matching);
+        matching.len()
+    }
+
+    fn delete_commands(&mut self, commands: &[String]) {
+        /* All of the commands are deleted as one, undoable at once */
+        if self.read_only {
+            return;
+        }
+        let commands: HashSet<&str> = commands.iter().map(String::as_str).collect();
         View::iter().for_each(|view| {
-            self.commands_mut(view).retain(|x| *x != command);
+            self.commands_mut(view)
+                .retain(|x| !commands.contains(x.as_str()));
         });
-        self.raw_history.retain(|x| *x != command);
+        self.align_timestamps();
+        let (deleted, kept): (Vec<_>, Vec<_>) = self
+            .raw_history
+            .iter()
+            .cloned()
+            .zip(self.timestamps.iter().cloned())
+            .zip(self.durations.iter().cloned())
+            .enumerate()
+            .partition(|(_, ((x, _), _))| commands.contains(x.as_str()));
+        if !deleted.is_empty() {
+            self.deleted.push(
+                deleted
+                    .into_iter()
+                    .map(|(index, ((x, timestamp), duration))| (index, x, timestamp, duration))
+                    .collect(),
+            );
+        }
+        self.raw_history = Vec::with_capacity(kept.len());
+        self.timestamps = Vec::with_capacity(kept.len());
+        self.durations = Vec::with_capacity(kept.len());
+        for (_, ((x, timestamp), duration)) in kept {
+            self.raw_history.push(x);
+            self.timestamps.push(timestamp);
+            self.durations.push(duration);
+        }
+    }
+
+    pub fn occurrence_at(&self, position: usize) -> Option<usize> {
+        /* Only the timeline lists every occurrence, in the order
+         * of the history, so which one a row is can be told there
+         */
+        if self.view != View::Timeline || self.dedupe {
+            return None;
+        }
+        let commands = self.commands(View::Timeline);
+        let command = commands.get(position)?;
+        Some(
+            commands[..position]
+                .iter()
+                .filter(|x| *x == command)
+                .count(),
+        )
+    }
+
+    pub fn occurrences(&self, command: &str) -> usize {
+        self.raw_history.iter().filter(|x| *x == command).count()
+    }
+
+    pub fn delete_occurrence(&mut self, command: String, nth: Option<usize>) {
+        /* Deletes the nth occurrence, counting from the oldest,
+         * or without one, the most recent occurrence only
+         */
+        if self.read_only {
+            return;
+        }
+        self.align_timestamps();
+        let mut positions = self
+            .raw_history
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| **x == command)
+            .map(|(index, _)| index);
+        let index = match nth {
+            Some(nth) => positions.nth(nth),
+            None => positions.next_back(),
+        };
+        if let Some(index) = index {
+            let deleted = self.raw_history.remove(index);
+            let timestamp = self.timestamps.remove(index);
+            let duration = self.durations.remove(index);
+            self.deleted
+                .push(vec![(index, deleted, timestamp, duration)]);
+        }
+        if !self.raw_history.contains(&command) {
+            self.delete_from_history(command);
+        }
+    }
+
+    pub fn undo_deletion(&mut self) -> bool {
+        /* Positions are ascending, so inserting in order
+         * puts every command back where it was
+         */
+        self.align_timestamps();
+        match self.deleted.pop() {
+            Some(deleted) => {
+                deleted
+                    .into_iter()
+                    .for_each(|(index, command, timestamp, duration)| {
+                        let index = index.min(self.raw_history.len());
+                        self.raw_history.insert(index, command);
+                        self.timestamps.insert(index, timestamp);
+                        self.durations.insert(index, duration);
+                    });
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn align_timestamps(&mut self) {
+        self.timestamps.resize(self.raw_history.len(), None);
+        self.durations.resize(self.raw_history.len(), None);
+    }
+
+    pub fn last_used(&self) -> HashMap<&str, u64> {
+        self.timestamped_history()
+            .filter_map(|(command, timestamp)| Some((command.as_str(), timestamp?)))
+            .fold(HashMap::new(), |mut last_used, (command, timestamp)| {
+                let latest = last_used.entry(command).or_insert(timestamp);
+                *latest = timestamp.max(*latest);
+                last_used
+            })
+    }
+
+    fn timestamped_history(&self) -> impl Iterator<Item = (&String, Option<u64>)> {
+        self.raw_history
+            .iter()
+            .enumerate()
+            .map(move |(index, command)| (command, self.timestamps.get(index).cloned().flatten()))
+    }
+
+    pub fn history_lines(&self) -> Vec<String> {
+        /* Timestamps are written back the way the shell writes them */
+        self.timestamped_history()
+            .zip(self.durations.iter().chain(iter::repeat(&None)))
+            .flat_map(
+                |((command, timestamp), duration)| match (timestamp, self.shell.as_str()) {
+                    (Some(timestamp), "zsh") => vec![format!(
+                        ": {}:{};{}",
+                        timestamp,
+                        duration.unwrap_or(0),
+                        hstr::zsh::escape_newlines(command)
+                    )],
+                    (None, "zsh") => vec![hstr::zsh::escape_newlines(command)],
+                    (Some(timestamp), _) => vec![format!("#{}", timestamp), command.clone()],
+                    (None, _) => vec![command.clone()],
+                },
+            )
+            .collect()
+    }
+
+    fn shown_history(&self) -> Vec<(String, Option<u64>)> {
+        /* Hidden commands stay in the raw history,
+         * so they survive writing the history back
+         */
+        self.timestamped_history()
+            .filter(|(cmd, _)| !(self.ignore_space && cmd.starts_with(char::is_whitespace)))
+            .filter(|(cmd, _)| !self.ignored.iter().any(|r| r.is_match(cmd)))
+            .map(|(cmd, timestamp)| (cmd.clone(), timestamp))
+            .collect()
     }
 
-    pub fn reload_history(&mut self) {
-        let commands = Commands {
-            sorted: sort::sort(self.raw_history.clone()),
-            favorites: io::read_from_home(&format!(".config/hstr-rs/.{}_favorites", self.shell))
-                .unwrap(),
-            all: self.raw_history.clone().into_iter().unique().collect(),
+    pub fn reload_history(&mut self) -> Result<(), Error> {
+        /* If the favorites file can't be read, the history is still
+         * reloaded, keeping the favorites that are in memory
+         */
+        let timestamped = self.shown_history();
+        let history = timestamped
+            .iter()
+            .map(|(cmd, _)| cmd.clone())
+            .collect::<Vec<String>>();
+        let (favorites, result) = if self.favorites_enabled {
+            let read = read_favorites(&self.shell)
+                .and_then(|f| Ok((f, read_tags(&self.shell)?, read_notes(&self.shell)?)));
+            match read {
+                Ok((favorites, tags, notes)) => {
+                    self.tags = tags;
+                    self.notes = notes;
+                    (favorites, Ok(()))
+                }
+                Err(e) => (self.to_restore.favorites.clone(), Err(e)),
+            }
+        } else {
+            (Vec::new(), Ok(()))
         };
+        let mut commands = Commands::new(&history, favorites);
+        if self.sort_mode != SortMode::Frequency || !sort::is_chronological(&timestamped) {
+            commands.resort(sort::ordered(self.sort_mode, &timestamped, sort::now()));
+        }
+        commands.this_dir = this_dir(&self.cwd, &history, self.directory_history.as_deref());
         self.to_restore = commands;
         self.commands = self.to_restore.clone();
+        self.index_favorites();
+        result
+    }
+
+    pub fn delete_word(&mut self, cursor: usize) -> usize {
+        /* Like Ctrl-W in bash, the word before the cursor is deleted,
+         * along with the whitespace after it, but not before it.
+         * The cursor is counted in characters, and where it ends up
+         * is returned.
+         */
+        let end = self
+            .query
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.query.len(), |(index, _)| index);
+        let trimmed = self.query[..end].trim_end();
+        let cut = match trimmed.rfind(char::is_whitespace) {
+            Some(index) => index + trimmed[index..].chars().next().unwrap().len_utf8(),
+            None => 0,
+        };
+        self.query.replace_range(cut..end, "");
+        self.query[..cut].chars().count()
+    }
+
+    pub fn clear_query(&mut self) {
+        self.query.clear();
+    }
+
+    pub fn remember_query(&mut self) {
+        /* Only remember a query before it gets shortened,
+         * not every intermediate query while deleting
+         */
+        const QUERY_HISTORY_SIZE: usize = 20;
+        self.query_recall = None;
+        if self.query.is_empty()
+            || matches!(self.query_history.last(), Some(last) if last.starts_with(&self.query))
+        {
+            return;
+        }
+        let query = self.query.clone();
+        self.query_history.retain(|x| *x != query);
+        self.query_history.push(query);
+        if self.query_history.len() > QUERY_HISTORY_SIZE {
+            self.query_history.remove(0);
+        }
+    }
+
+    pub fn previous_query(&mut self) {
+        let index = match self.query_recall {
+            Some(index) => index.saturating_sub(1),
+            None => {
+                self.remember_query();
+                match self.query_history.len() {
+                    0 => return,
+                    len if self.query_history[len - 1] == self.query && len > 1 => len - 2,
+                    len => len - 1,
+                }
+            }
+        };
+        self.query_recall = Some(index);
+        self.query = self.query_history[index].clone();
+    }
+
+    pub fn next_query(&mut self) {
+        if let Some(index) = self.query_recall {
+            let index = (index + 1).min(self.query_history.len() - 1);
+            self.query_recall = Some(index);
+            self.query = self.query_history[index].clone();
+        }
+    }
+
+    pub fn settings(&self) -> Vec<String> {
+        vec![
+            format!("search_mode={}", self.search_mode.as_ref()),
+            format!("case_sensitivity={}", self.case_sensitivity),
+            format!("reversed={}", self.reversed),
+            format!("sort_mode={}", self.sort_mode.as_ref()),
+            format!("view={}", self.view.as_ref()),
+        ]
+    }
+
+    pub fn apply_settings(&mut self, settings: &[String]) {
+        settings
+            .iter()
+            .filter_map(|setting| setting.split_once('='))
+            .for_each(|(key, value)| match key {
+                "search_mode" => {
+                    if let Ok(search_mode) = value.parse() {
+                        self.search_mode = search_mode;
+                    }
+                }
+                "case_sensitivity" => {
+                    if let Ok(case_sensitivity) = value.parse() {
+                        self.case_sensitivity = case_sensitivity;
+                    }
+                }
+                "reversed" => {
+                    if let Ok(reversed) = value.parse() {
+                        self.reversed = reversed;
+                    }
+                }
+                "sort_mode" => {
+                    if let Ok(sort_mode) = value.parse() {
+                        self.sort_mode = sort_mode;
+                    }
+                }
+                "view" => {
+                    if let Ok(view) = value.parse() {
+                        self.view = view;
+                    }
+                }
+                _ => {}
+            });
+        if !self.is_available(self.view) {
+            self.view = View::Sorted;
+        }
+    }
+
+    pub fn is_case_sensitive(&self) -> bool {
+        /* Smart case: an uppercase letter in the query means it matters */
+        if self.smart_case {
+            self.query.chars().any(char::is_uppercase)
+        } else {
+            self.case_sensitivity
+        }
     }
 
     pub fn toggle_case(&mut self) {
-        self.case_sensitivity = !self.case_sensitivity;
+        /* Toggling overrides smart case, flipping what it has picked */
+        self.case_sensitivity = !self.is_case_sensitive();
+        self.smart_case = false;
+    }
+
+    pub fn toggle_privileged_only(&mut self) {
+        self.privileged_only = !self.privileged_only;
+    }
+
+    pub fn set_length_range(&mut self, range: &str) -> bool {
+        match parse_length_range(range) {
+            Some((min, max)) => {
+                self.min_length = min;
+                self.max_length = max;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn length_range(&self) -> Option<String> {
+        /* Written the way it is typed, like 20-, -10 or 5-40 */
+        let bound = |length: Option<usize>| length.map(|x| x.to_string()).unwrap_or_default();
+        match (self.min_length, self.max_length) {
+            (None, None) => None,
+            (min, max) => Some(format!("{}-{}", bound(min), bound(max))),
+        }
+    }
+
+    pub fn toggle_dedupe(&mut self) {
+        self.dedupe = !self.dedupe;
+    }
+
+    pub fn toggle_reversed(&mut self) {
+        self.reversed = !self.reversed;
     }
 
     pub fn toggle_search_mode(&mut self) {
-        self.search_mode = match (self.search_mode as u8 + 1) % 3 {
+        self.search_mode = match (self.search_mode as u8 + 1) % 4 {
             0 => SearchMode::Exact,
             1 => SearchMode::Regex,
             2 => SearchMode::Fuzzy,
+            3 => SearchMode::Loose,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn toggle_sort_mode(&mut self) {
+        self.sort_mode = match (self.sort_mode as u8 + 1) % 5 {
+            0 => SortMode::Frequency,
+            1 => SortMode::Recency,
+            2 => SortMode::Frecency,
+            3 => SortMode::Alphabetical,
+            4 => SortMode::Length,
             _ => unreachable!(),
         }
     }
 
     pub fn toggle_view(&mut self) {
-        self.view = match (self.view as u8 + 1) % 3 {
+        /* Every tag is a stop of its own in the favorites view */
+        if self.view == View::Favorites {
+            self.tag = self.next_tag();
+            if self.tag.is_some() {
+                return;
+            }
+        }
+        self.view = match (self.view as u8 + 1) % 6 {
             0 => View::Sorted,
             1 => View::Favorites,
             2 => View::All,
+            3 => View::ThisDir,
+            4 => View::Combined,
+            5 => View::Timeline,
             _ => unreachable!(),
+        };
+        if !self.is_available(self.view) {
+            self.toggle_view();
+        }
+    }
+
+    fn next_tag(&self) -> Option<String> {
+        let names = favorites::names(&self.tags);
+        let next = match &self.tag {
+            Some(tag) => names.iter().position(|name| name == tag).map(|i| i + 1),
+            None => Some(0),
+        };
+        next.and_then(|i| names.get(i)).map(|name| name.to_string())
+    }
+
+    pub fn start_in(&mut self, view: View) {
+        /* A view that isn't there, like favorites with
+         * --no-favorites, leaves the view as it is
+         */
+        if self.is_available(view) {
+            self.view = view;
+        }
+    }
+
+    fn is_available(&self, view: View) -> bool {
+        match view {
+            View::Favorites => self.favorites_enabled,
+            View::ThisDir => self.directory_history.is_some(),
+            _ => true,
+        }
+    }
+}
+
+fn read_tags(shell: &str) -> Result<Tags, Error> {
+    let lines = io::read_from_home(tags_path(shell))?;
+    Ok(favorites::parse_tags(&lines))
+}
+
+fn read_notes(shell: &str) -> Result<Notes, Error> {
+    let lines = io::read_from_home(notes_path(shell))?;
+    Ok(favorites::parse_notes(&lines))
+}
+
+pub fn notes_path(shell: &str) -> String {
+    format!(".config/hstr-rs/.{}_notes", shell)
+}
+
+pub fn favorites_path(shell: &str) -> String {
+    format!(".config/hstr-rs/.{}_favorites", shell)
+}
+
+pub fn tags_path(shell: &str) -> String {
+    format!(".config/hstr-rs/.{}_tags", shell)
+}
+
+fn read_favorites(shell: &str) -> Result<Vec<String>, Error> {
+    Ok(io::read_from_home(favorites_path(shell))?
+        .into_iter()
+        .filter_map(hstr::tidy)
+        .collect())
+}
+
+pub fn parse_patterns(lines: &[String]) -> Result<Vec<Regex>, regex::Error> {
+    /* One pattern per line, blank lines and comments are skipped */
+    lines
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Regex::new)
+        .collect()
+}
+
+pub fn loose_pattern(query: &str) -> String {
+    /* Whitespace doesn't count: `gitpush` finds `git  push` */
+    query
+        .chars()
+        .filter(|ch| !ch.is_whitespace())
+        .map(|ch| escape(&ch.to_string()))
+        .join(r"\s*")
+}
+
+pub fn rank(command: &str, search_regex: &Regex) -> u8 {
+    /* Lower is better: matches at the start of the command
+     * come first, then matches at the start of a word, then
+     * the rest. The sort is stable, so ties keep their order.
+     */
+    search_regex
+        .find_iter(command)
+        .map(|m| match command[..m.start()].chars().last() {
+            None => 0,
+            Some(ch) if !ch.is_alphanumeric() => 1,
+            Some(_) => 2,
+        })
+        .min()
+        .unwrap_or(u8::MAX)
+}
+
+pub fn parse_length_range(range: &str) -> Option<(Option<usize>, Option<usize>)> {
+    /* A single number is the least length; an empty range clears it */
+    let (min, max) = range.trim().split_once('-').unwrap_or((range, ""));
+    let bound = |x: &str| match x.trim() {
+        "" => Some(None),
+        x => x.parse().ok().map(Some),
+    };
+    match (bound(min)?, bound(max)?) {
+        (Some(min), Some(max)) if min > max => None,
+        range => Some(range),
+    }
+}
+
+fn fits_length(command: &str, min: Option<usize>, max: Option<usize>) -> bool {
+    let length = command.chars().count();
+    min.unwrap_or(0) <= length && length <= max.unwrap_or(usize::MAX)
+}
+
+pub fn is_privileged(command: &str) -> bool {
+    const PRIVILEGE_PROGRAMS: [&str; 3] = ["sudo", "doas", "pkexec"];
+    match command.split_whitespace().next() {
+        Some(program) => PRIVILEGE_PROGRAMS.contains(&program),
+        None => false,
+    }
+}
+
+fn this_dir(
+    cwd: &Path,
+    raw_history: &[String],
+    directory_history: Option<&[(String, String)]>,
+) -> Vec<String> {
+    match directory_history {
+        Some(directory_history) => {
+            let in_history: HashSet<&str> = raw_history.iter().map(String::as_str).collect();
+            sort::sort(
+                hstr::commands_under(cwd, directory_history)
+                    .into_iter()
+                    .filter(|cmd| in_history.contains(cmd.as_str()))
+                    .collect(),
+            )
         }
+        None => Vec::new(),
     }
 }
 
+fn dedupe(commands: &[String]) -> Vec<String> {
+    /* Commands that only differ in whitespace are duplicates,
+     * the first one, which is ranked higher, is kept
+     */
+    commands
+        .iter()
+        .unique_by(|cmd| normalized(cmd))
+        .cloned()
+        .collect()
+}
+
+fn normalized(cmd: &str) -> Vec<&str> {
+    cmd.split_whitespace().collect()
+}
+
+fn words(cmd: &str) -> String {
+    /* Words have no whitespace, so joining them keeps them apart */
+    cmd.split_whitespace().join(" ")
+}
+
+fn index_favorites(favorites: &[String]) -> HashMap<String, String> {
+    /* Of favorites with the same words, the first one counts */
+    let mut index = HashMap::new();
+    favorites.iter().for_each(|favorite| {
+        index
+            .entry(words(favorite))
+            .or_insert_with(|| favorite.clone());
+    });
+    index
+}
+
+fn combine(favorites: &[String], sorted: &[String], all: &[String]) -> Vec<String> {
+    /* Favorites come first, then the history in sorted order,
+     * followed by whatever the sorted view leaves out
+     */
+    favorites
+        .iter()
+        .chain(sorted)
+        .chain(all)
+        .unique()
+        .cloned()
+        .collect()
+}
+
 #[derive(Clone)]
 pub struct Commands {
     pub sorted: Vec<String>,
     pub favorites: Vec<String>,
     pub all: Vec<String>,
+    pub this_dir: Vec<String>,
+    pub combined: Vec<String>,
+    /* The history as it is in the file, duplicates and all */
+    pub timeline: Vec<String>,
 }
 
 impl Commands {
-    pub fn from_history(shell: &str, history: &[String]) -> Self {
+    pub fn new(history: &[String], favorites: Vec<String>) -> Self {
+        let sorted = sort::sort(history.to_vec());
+        let all: Vec<String> = history.iter().cloned().unique().collect();
         Self {
-            sorted: sort::sort(history.to_vec()),
-            favorites: io::read_from_home(format!(".config/hstr-rs/.{}_favorites", shell)).unwrap(),
-            all: history.to_vec().into_iter().unique().collect(),
+            combined: combine(&favorites, &sorted, &all),
+            sorted,
+            favorites,
+            all,
+            this_dir: Vec::new(),
+            timeline: history.to_vec(),
         }
     }
+
+    pub fn resort(&mut self, sorted: Vec<String>) {
+        self.combined = combine(&self.favorites, &sorted, &self.all);
+        self.sorted = sorted;
+    }
+
+    pub fn from_history(shell: &str, history: &[String]) -> Result<Self, Error> {
+        Ok(Self::new(history, read_favorites(shell)?))
+    }
 }
 
-#[derive(Clone, Copy, Debug, EnumIter, Eq, Hash, PartialEq)]
+pub const VIEWS: &[&str] = &[
+    "sorted",
+    "favorites",
+    "all",
+    "this_dir",
+    "combined",
+    "timeline",
+];
+
+#[derive(AsRefStr, Clone, Copy, Debug, EnumIter, EnumString, Eq, Hash, PartialEq)]
+#[strum(serialize_all = "snake_case")]
 pub enum View {
     Sorted = 0,
     Favorites = 1,
     All = 2,
+    ThisDir = 3,
+    Combined = 4,
+    Timeline = 5,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(AsRefStr, Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
 pub enum SearchMode {
     Exact = 0,
     Regex = 1,
     Fuzzy = 2,
+    Loose = 3,
 }
 
 #[cfg(test)]
@@ -224,9 +1175,16 @@ pub mod fixtures {
         let fake_commands = Commands {
             all: fake_history.clone(),
             favorites: Vec::new(),
-            sorted: fake_history,
+            sorted: fake_history.clone(),
+            this_dir: Vec::new(),
+            combined: fake_history.clone(),
+            timeline: fake_history.clone(),
         };
-        state.commands = fake_commands;
+        state.commands = fake_commands.clone();
+        state.to_restore = fake_commands;
+        state.index_favorites();
+        state.tags = Tags::new();
+        state.notes = Notes::new();
         state
     }
 }
@@ -246,7 +1204,10 @@ mod tests {
         case("SPAM", vec!["cat SPAM"], SearchMode::Exact, true),
         case("[0-9]+", vec!["git rebase -i HEAD~2", "ping -c 10 www.google.com", "xfce4-panel -r", "make -j4"], SearchMode::Regex, false),
         case("šp", vec!["echo šampion"], SearchMode::Fuzzy, false),
-        case("hwk", vec!["nano .github/workflows/build.yml", "cd /home/bwk/"], SearchMode::Fuzzy, false)
+        case("hwk", vec!["cd /home/bwk/", "nano .github/workflows/build.yml"], SearchMode::Fuzzy, false),
+        case("ping-c10", vec!["ping -c 10 www.google.com"], SearchMode::Loose, false),
+        case("cat  spam", vec!["cat spam", "cat SPAM"], SearchMode::Loose, false),
+        case("make-j4", vec!["make -j4"], SearchMode::Loose, false)
     )]
     fn search(
         query: &str,
@@ -262,12 +1223,61 @@ mod tests {
         assert_eq!(fake_state.commands(fake_state.view), expected);
     }
 
+    #[rstest(
+        query,
+        search_mode,
+        case("git", SearchMode::Exact),
+        case("git", SearchMode::Regex),
+        case("git", SearchMode::Fuzzy),
+        case("g it", SearchMode::Loose)
+    )]
+    fn search_timeline(query: &str, search_mode: SearchMode) {
+        /* Ranking would put the prefix matches first */
+        let history: Vec<String> = ["make git", "git push", "ls", "git pull", "git push"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        let commands = Commands::new(&history, Vec::new());
+        let mut state = State::from_commands("", query, history, commands);
+        state.timestamps = vec![Some(1), Some(5), Some(2), Some(3), Some(4)];
+        state.search_mode = search_mode;
+        state.view = View::Timeline;
+        state.search();
+        assert_eq!(
+            state.commands(View::Timeline),
+            vec!["make git", "git push", "git pull", "git push"]
+        );
+    }
+
+    #[rstest(
+        timestamps,
+        expected,
+        case(vec![None, None], vec!["git push", "git pull"]),
+        case(vec![Some(100), Some(200)], vec!["git pull", "git push"]),
+        case(vec![Some(200), Some(100)], vec!["git push", "git pull"]),
+        case(vec![Some(100), Some(100)], vec!["git pull", "git push"])
+    )]
+    fn fuzzy_ties(timestamps: Vec<Option<u64>>, expected: Vec<&str>) {
+        /* Both commands score the same for the query */
+        let history: Vec<String> = ["git push", "git pull"].iter().map(|&x| x.into()).collect();
+        let commands = Commands::new(&history, Vec::new());
+        let mut state = State::from_commands("", "gp", history, commands);
+        state.timestamps = timestamps;
+        state.view = View::All;
+        state.search_mode = SearchMode::Fuzzy;
+        state.search();
+        assert_eq!(state.commands(View::All), expected);
+    }
+
     #[rstest(
         view,
         expected,
         case(View::Sorted, fake_history()),
         case(View::Favorites, Vec::new()),
-        case(View::All, fake_history())
+        case(View::All, fake_history()),
+        case(View::ThisDir, Vec::new()),
+        case(View::Combined, fake_history()),
+        case(View::Timeline, fake_history())
     )]
     fn get_commands(view: View, expected: Vec<String>, mut fake_state: State) {
         fake_state.view = view;
@@ -275,6 +1285,27 @@ mod tests {
         assert_eq!(commands, expected);
     }
 
+    #[rstest(
+        query,
+        search_mode,
+        expected,
+        case("[0-9", SearchMode::Regex, true),
+        case("[0-9]", SearchMode::Regex, false),
+        case("[0-9", SearchMode::Exact, false),
+        case("[0-9", SearchMode::Fuzzy, false)
+    )]
+    fn search_invalid_regex(
+        query: &str,
+        search_mode: SearchMode,
+        expected: bool,
+        mut fake_state: State,
+    ) {
+        fake_state.search_mode = search_mode;
+        fake_state.query = String::from(query);
+        fake_state.search();
+        assert_eq!(fake_state.invalid_regex, expected);
+    }
+
     #[rstest(
         query,
         search_mode,
@@ -300,6 +1331,24 @@ mod tests {
         assert_eq!(regex.unwrap_or(Regex::new("").unwrap()).as_str(), expected);
     }
 
+    #[rstest(search_mode, case(SearchMode::Exact), case(SearchMode::Regex))]
+    fn search_regex_cache(search_mode: SearchMode, mut fake_state: State) {
+        fake_state.query = String::from("SPAM");
+        fake_state.search_mode = search_mode;
+        let insensitive = fake_state.create_search_regex().unwrap();
+        assert!(insensitive.is_match("cat spam"));
+        let cached = fake_state.create_search_regex().unwrap();
+        assert_eq!(cached.as_str(), insensitive.as_str());
+        fake_state.toggle_case();
+        let sensitive = fake_state.create_search_regex().unwrap();
+        assert!(!sensitive.is_match("cat spam"));
+        fake_state.query = String::from("spam");
+        assert!(fake_state
+            .create_search_regex()
+            .unwrap()
+            .is_match("cat spam"));
+    }
+
     #[rstest(
         command,
         case(String::from("cat spam")),
@@ -313,6 +1362,134 @@ mod tests {
         assert!(!fake_state.commands(View::Favorites).contains(&command));
     }
 
+    #[rstest(
+        command,
+        favorite,
+        expected,
+        case("cargo fmt ;;; cargo test", true, "cargo fmt && cargo test"),
+        case("echo ';;;' x", false, "echo ';;;' x"),
+        case("echo ';;;' x", true, "echo ' && ' x")
+    )]
+    fn assemble(command: &str, favorite: bool, expected: &str, mut fake_state: State) {
+        if favorite {
+            fake_state.add_or_rm_fav(command.to_string());
+        }
+        assert_eq!(fake_state.assemble(command, " && "), expected);
+    }
+
+    #[rstest()]
+    fn add_or_rm_fav_in_narrowed_view(mut fake_state: State) {
+        /* Only "make -j4" is shown, but the other favorite must stay */
+        fake_state.add_or_rm_fav(String::from("cat spam"));
+        fake_state.add_or_rm_fav(String::from("make -j4"));
+        fake_state.view = View::Favorites;
+        fake_state.query = String::from("make");
+        fake_state.rerun_search();
+        fake_state.add_or_rm_fav(String::from("ls -la"));
+        fake_state.add_or_rm_fav(String::from("make -j4"));
+        assert_eq!(fake_state.commands(View::Favorites), vec!["ls -la"]);
+        assert_eq!(fake_state.to_restore.favorites, vec!["cat spam", "ls -la"]);
+    }
+
+    #[rstest(
+        view,
+        search_mode,
+        query,
+        case(View::Favorites, SearchMode::Exact, "a"),
+        case(View::Favorites, SearchMode::Fuzzy, "mk"),
+        case(View::Sorted, SearchMode::Regex, "^c")
+    )]
+    fn favorites_keep_insertion_order(
+        view: View,
+        search_mode: SearchMode,
+        query: &str,
+        mut fake_state: State,
+    ) {
+        /* The favorites file is written in the order the favorites
+         * were added, whatever the list on screen looks like
+         */
+        fake_state.view = view;
+        fake_state.search_mode = search_mode;
+        ["make -j4", "cat spam", "ls -la", "cd /tmp"]
+            .iter()
+            .for_each(|&cmd| {
+                fake_state.add_or_rm_fav(cmd.into());
+                fake_state.query = String::from(query);
+                fake_state.rerun_search();
+            });
+        fake_state.add_or_rm_fav(String::from("cat spam"));
+        fake_state.add_or_rm_fav(String::from("cat spam"));
+        assert_eq!(
+            fake_state.to_restore.favorites,
+            vec!["make -j4", "ls -la", "cd /tmp", "cat spam"]
+        );
+    }
+
+    #[rstest(
+        tags,
+        expected,
+        case("db", vec!["db"]),
+        case(" work  db work", vec!["db", "work"]),
+        case("", vec![]),
+        case("a=b", vec![])
+    )]
+    fn tag_favorite(tags: &str, expected: Vec<&str>, mut fake_state: State) {
+        fake_state.tag_favorite(String::from("psql"), "old");
+        fake_state.tag_favorite(String::from("psql"), tags);
+        assert!(fake_state.cmd_in_fav("psql"));
+        assert_eq!(fake_state.tags_of("psql"), expected);
+        fake_state.add_or_rm_fav(String::from("psql"));
+        assert!(fake_state.tags.is_empty());
+    }
+
+    #[rstest(
+        note,
+        expected,
+        case("database  shell ", Some("database shell")),
+        case("", None)
+    )]
+    fn annotate(note: &str, expected: Option<&str>, mut fake_state: State) {
+        fake_state.annotate(String::from("psql"), "old");
+        fake_state.annotate(String::from("psql"), note);
+        assert!(fake_state.cmd_in_fav("psql"));
+        assert_eq!(fake_state.note_of("psql"), expected);
+        fake_state.add_or_rm_fav(String::from("psql"));
+        assert!(fake_state.notes.is_empty());
+    }
+
+    #[rstest(
+        favorite,
+        query,
+        search_mode,
+        case("make -j4", "production", SearchMode::Exact),
+        case("make -j4", "prod.*on", SearchMode::Regex),
+        case("make -j4", "prdctn", SearchMode::Fuzzy),
+        case("make  -j4", "production", SearchMode::Exact),
+        case("make  -j4", "prdctn", SearchMode::Fuzzy)
+    )]
+    fn search_notes(favorite: &str, query: &str, search_mode: SearchMode, mut fake_state: State) {
+        fake_state.annotate(String::from(favorite), "builds for production");
+        fake_state.search_mode = search_mode;
+        fake_state.query = String::from(query);
+        fake_state.rerun_search();
+        assert_eq!(fake_state.commands(fake_state.view), vec!["make -j4"]);
+    }
+
+    #[rstest(
+        variant,
+        case("git  status"),
+        case(" git status"),
+        case("git\tstatus ")
+    )]
+    fn add_or_rm_fav_whitespace_variant(variant: &str, mut fake_state: State) {
+        fake_state.add_or_rm_fav(String::from("git status"));
+        assert!(fake_state.cmd_in_fav(variant));
+        fake_state.add_or_rm_fav(String::from(variant));
+        assert!(fake_state.commands(View::Favorites).is_empty());
+        fake_state.add_or_rm_fav(String::from(variant));
+        assert_eq!(fake_state.commands(View::Favorites), vec![variant]);
+    }
+
     #[rstest(
         command,
         case(String::from("cat spam")),
@@ -324,26 +1501,408 @@ mod tests {
         assert!(!fake_state.commands(fake_state.view).contains(&command));
     }
 
+    #[rstest(
+        query,
+        expected_deleted,
+        expected_history,
+        case("cat", 1, vec!["ls", "ls", "pwd"]),
+        case("l", 1, vec!["cat spam", "pwd"]),
+        case("p", 2, vec!["ls", "ls"]),
+        case("", 0, vec!["ls", "cat spam", "ls", "pwd"])
+    )]
+    fn delete_matching(
+        query: &str,
+        expected_deleted: usize,
+        expected_history: Vec<&str>,
+        mut fake_state: State,
+    ) {
+        let history: Vec<String> = ["ls", "cat spam", "ls", "pwd"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        fake_state.raw_history = history.clone();
+        fake_state.reload_history().unwrap();
+        fake_state.query = query.to_string();
+        fake_state.search();
+        assert_eq!(fake_state.delete_matching(), expected_deleted);
+        assert_eq!(fake_state.raw_history, expected_history);
+        View::iter().for_each(|view| {
+            assert!(fake_state
+                .commands(view)
+                .iter()
+                .all(|cmd| expected_history.contains(&cmd.as_str())));
+        });
+        fake_state.undo_deletion();
+        assert_eq!(fake_state.raw_history, history);
+    }
+
+    #[rstest()]
+    fn delete_occurrence(mut fake_state: State) {
+        fake_state.raw_history = ["ls", "cat spam", "ls", "pwd", "ls", "cat spam"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        fake_state.delete_occurrence(String::from("ls"), None);
+        assert_eq!(
+            fake_state.raw_history,
+            vec!["ls", "cat spam", "ls", "pwd", "cat spam"]
+        );
+        assert_eq!(fake_state.occurrences("ls"), 2);
+        fake_state.delete_occurrence(String::from("pwd"), None);
+        assert_eq!(
+            fake_state.raw_history,
+            vec!["ls", "cat spam", "ls", "cat spam"]
+        );
+        fake_state.delete_occurrence(String::from("cat spam"), None);
+        assert_eq!(fake_state.raw_history, vec!["ls", "cat spam", "ls"]);
+        assert!(fake_state
+            .commands(View::Sorted)
+            .contains(&String::from("cat spam")));
+        fake_state.delete_occurrence(String::from("cat spam"), None);
+        assert_eq!(fake_state.raw_history, vec!["ls", "ls"]);
+        assert!(!fake_state
+            .commands(View::Sorted)
+            .contains(&String::from("cat spam")));
+    }
+
+    #[rstest(
+        position,
+        expected_history,
+        case(0, vec!["cat spam", "ls", "pwd"]),
+        case(2, vec!["ls", "cat spam", "pwd"])
+    )]
+    fn delete_occurrence_in_timeline(position: usize, expected_history: Vec<&str>) {
+        let history: Vec<String> = ["ls", "cat spam", "ls", "pwd"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        let commands = Commands::new(&history, Vec::new());
+        let mut state = State::from_commands("bash", "", history, commands);
+        state.view = View::Timeline;
+        state.search();
+        let nth = state.occurrence_at(position);
+        state.delete_occurrence(String::from("ls"), nth);
+        assert_eq!(state.raw_history, expected_history);
+    }
+
+    #[rstest()]
+    fn deletion_keeps_timestamps(mut fake_state: State) {
+        fake_state.raw_history = ["ls", "cat spam", "ls", "pwd"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        fake_state.timestamps = vec![Some(1), Some(2), Some(3), None];
+        fake_state.durations = vec![Some(4), Some(5), Some(6), None];
+        fake_state.delete_from_history(String::from("cat spam"));
+        fake_state.delete_occurrence(String::from("ls"), None);
+        assert_eq!(fake_state.history_lines(), vec!["#1", "ls", "pwd"]);
+        fake_state.undo_deletion();
+        fake_state.undo_deletion();
+        assert_eq!(
+            fake_state.history_lines(),
+            vec!["#1", "ls", "#2", "cat spam", "#3", "ls", "pwd"]
+        );
+        assert_eq!(fake_state.durations, vec![Some(4), Some(5), Some(6), None]);
+    }
+
+    #[rstest(
+        favorites,
+        expected,
+        case(vec![], vec!["ls", "pwd", "cat spam"]),
+        case(vec!["pwd"], vec!["pwd", "ls", "cat spam"]),
+        case(vec!["make", "ls"], vec!["make", "ls", "pwd", "cat spam"])
+    )]
+    fn combine(favorites: Vec<&str>, expected: Vec<&str>) {
+        let history: Vec<String> = ["cat spam", "ls", "pwd", "ls"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        let commands = Commands::new(&history, favorites.into_iter().map(String::from).collect());
+        assert_eq!(commands.combined, expected);
+    }
+
+    #[rstest()]
+    fn combined_view_follows_favorites(mut fake_state: State) {
+        fake_state.raw_history = fake_history();
+        fake_state.view = View::Combined;
+        fake_state.add_or_rm_fav(String::from("make -j4"));
+        fake_state.add_or_rm_fav(String::from("not in history"));
+        assert_eq!(fake_state.commands(View::Combined).len(), 27);
+        fake_state.add_or_rm_fav(String::from("make -j4"));
+        fake_state.add_or_rm_fav(String::from("not in history"));
+        assert_eq!(fake_state.commands(View::Combined), &fake_history()[..]);
+    }
+
+    #[rstest(ignore_space, expected, case(true, 2), case(false, 3))]
+    fn hide_leading_space(ignore_space: bool, expected: usize, mut fake_state: State) {
+        fake_state.raw_history = ["ls", " cat secret", "pwd"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        fake_state.ignore_space = ignore_space;
+        fake_state.reload_history().unwrap();
+        [View::Sorted, View::All, View::Combined]
+            .iter()
+            .for_each(|&view| assert_eq!(fake_state.commands(view).len(), expected));
+        fake_state.delete_from_history(String::from("pwd"));
+        assert_eq!(fake_state.raw_history, vec!["ls", " cat secret"]);
+    }
+
+    #[rstest(
+        patterns,
+        expected,
+        case(vec![], 26),
+        case(vec!["^git "], 21),
+        case(vec!["# no comments", "", "^ls", "spam"], 21),
+        case(vec!["(?i)^cat spam$"], 24)
+    )]
+    fn parse_patterns(patterns: Vec<&str>, expected: usize, mut fake_state: State) {
+        let patterns: Vec<String> = patterns.iter().map(|&x| x.into()).collect();
+        fake_state.raw_history = fake_history();
+        fake_state.ignored = super::parse_patterns(&patterns).unwrap();
+        fake_state.reload_history().unwrap();
+        [View::All, View::Combined]
+            .iter()
+            .for_each(|&view| assert_eq!(fake_state.commands(view).len(), expected));
+        assert_eq!(fake_state.raw_history, fake_history());
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let patterns = vec![String::from("^ls"), String::from("[0-9")];
+        assert!(super::parse_patterns(&patterns).is_err());
+    }
+
+    #[rstest()]
+    fn history_lines_for_zsh(mut fake_state: State) {
+        fake_state.shell = String::from("zsh");
+        fake_state.raw_history = vec![String::from("ls"), String::from("pwd")];
+        fake_state.timestamps = vec![Some(1330648651), None];
+        assert_eq!(fake_state.history_lines(), vec![": 1330648651:0;ls", "pwd"]);
+        fake_state.durations = vec![Some(12), None];
+        assert_eq!(
+            fake_state.history_lines(),
+            vec![": 1330648651:12;ls", "pwd"]
+        );
+        fake_state.durations = Vec::new();
+        fake_state.raw_history = vec![String::from("for i in 1 2\ndo echo $i\ndone")];
+        fake_state.timestamps = vec![Some(1330648651)];
+        assert_eq!(
+            fake_state.history_lines(),
+            vec![": 1330648651:0;for i in 1 2\\\ndo echo $i\\\ndone"]
+        );
+    }
+
+    #[rstest(
+        sort_mode,
+        expected,
+        case(SortMode::Frequency, vec!["make", "ls"]),
+        case(SortMode::Recency, vec!["ls", "make"]),
+        case(SortMode::Frecency, vec!["ls", "make"]),
+        case(SortMode::Alphabetical, vec!["ls", "make"]),
+        case(SortMode::Length, vec!["ls", "make"])
+    )]
+    fn reload_with_sort_mode(sort_mode: SortMode, expected: Vec<&str>, mut fake_state: State) {
+        fake_state.raw_history = ["make", "make", "ls"].iter().map(|&x| x.into()).collect();
+        fake_state.timestamps = vec![Some(0), Some(0), Some(sort::now())];
+        fake_state.sort_mode = sort_mode;
+        fake_state.reload_history().unwrap();
+        assert_eq!(fake_state.commands(View::Sorted), expected);
+    }
+
+    #[rstest(
+        before,
+        after,
+        case(View::Sorted, View::All),
+        case(View::All, View::Combined),
+        case(View::Combined, View::Timeline),
+        case(View::Timeline, View::Sorted)
+    )]
+    fn toggle_view_without_favorites(before: View, after: View, mut fake_state: State) {
+        fake_state.favorites_enabled = false;
+        fake_state.directory_history = None;
+        fake_state.view = before;
+        fake_state.toggle_view();
+        assert_eq!(fake_state.view, after);
+    }
+
+    #[rstest()]
+    fn favorites_disabled(mut fake_state: State) {
+        fake_state.favorites_enabled = false;
+        fake_state.add_or_rm_fav(String::from("cat spam"));
+        assert!(fake_state.commands(View::Favorites).is_empty());
+        fake_state.apply_settings(&[String::from("view=favorites")]);
+        assert_eq!(fake_state.view, View::Sorted);
+        fake_state.reload_history().unwrap();
+        assert!(fake_state.commands(View::Favorites).is_empty());
+    }
+
+    #[rstest()]
+    fn import_favorites(mut fake_state: State) {
+        fake_state.add_or_rm_fav(String::from("cargo test"));
+        let imported = fake_state.import_favorites(
+            ["make -j4", "", "cargo test", "make -j4", "cd /tmp  "]
+                .iter()
+                .map(|&x| x.into())
+                .collect(),
+        );
+        assert_eq!(imported, 2);
+        assert_eq!(
+            fake_state.commands(View::Favorites),
+            vec!["cargo test", "make -j4", "cd /tmp"]
+        );
+        assert_eq!(
+            fake_state.to_restore.favorites,
+            vec!["cargo test", "make -j4", "cd /tmp"]
+        );
+    }
+
+    #[rstest()]
+    fn read_only(mut fake_state: State) {
+        fake_state.read_only = true;
+        fake_state.raw_history = vec![String::from("cat spam"), String::from("ls")];
+        fake_state.add_or_rm_fav(String::from("cat spam"));
+        fake_state.delete_from_history(String::from("cat spam"));
+        fake_state.delete_occurrence(String::from("ls"), None);
+        assert!(fake_state.commands(View::Favorites).is_empty());
+        assert!(fake_state
+            .commands(View::Sorted)
+            .contains(&String::from("cat spam")));
+        assert_eq!(fake_state.raw_history, vec!["cat spam", "ls"]);
+        assert!(!fake_state.undo_deletion());
+    }
+
+    #[rstest()]
+    fn undo_deletion(mut fake_state: State) {
+        let history = ["ls", "cat spam", "ls", "pwd", "ls", "cat spam"]
+            .iter()
+            .map(|&x| x.into())
+            .collect::<Vec<String>>();
+        fake_state.raw_history = history.clone();
+        fake_state.delete_from_history(String::from("ls"));
+        fake_state.delete_occurrence(String::from("cat spam"), None);
+        fake_state.delete_from_history(String::from("git push"));
+        assert_eq!(fake_state.raw_history, vec!["cat spam", "pwd"]);
+        assert!(fake_state.undo_deletion());
+        assert_eq!(fake_state.raw_history, vec!["cat spam", "pwd", "cat spam"]);
+        fake_state.reload_history().unwrap();
+        assert!(fake_state.undo_deletion());
+        assert_eq!(fake_state.raw_history, history);
+        assert!(!fake_state.undo_deletion());
+        assert_eq!(fake_state.raw_history, history);
+    }
+
     #[rstest(
         before,
         after,
         case(View::Sorted, View::Favorites),
         case(View::Favorites, View::All),
-        case(View::All, View::Sorted)
+        case(View::All, View::Combined),
+        case(View::Combined, View::Timeline),
+        case(View::Timeline, View::Sorted)
     )]
     fn toggle_view(before: View, after: View) {
         let mut state = State::new("");
+        state.directory_history = None;
+        state.tags.clear();
+        state.view = before;
+        state.toggle_view();
+        assert_eq!(state.view, after);
+    }
+
+    #[rstest()]
+    fn toggle_view_through_tags(mut fake_state: State) {
+        fake_state.directory_history = None;
+        fake_state.tag_favorite(String::from("make -j4"), "build");
+        fake_state.tag_favorite(String::from("cat spam"), "misc build");
+        fake_state.add_or_rm_fav(String::from("ls -la"));
+        let mut stops = Vec::new();
+        (0..4).for_each(|_| {
+            fake_state.toggle_view();
+            fake_state.rerun_search();
+            stops.push((
+                fake_state.view,
+                fake_state.tag.clone(),
+                fake_state.commands(fake_state.view).len(),
+            ));
+        });
+        assert_eq!(
+            stops,
+            vec![
+                (View::Favorites, None, 3),
+                (View::Favorites, Some(String::from("build")), 2),
+                (View::Favorites, Some(String::from("misc")), 1),
+                (View::All, None, fake_history().len()),
+            ]
+        );
+    }
+
+    #[rstest(
+        before,
+        after,
+        case(View::Sorted, View::Favorites),
+        case(View::Favorites, View::All),
+        case(View::All, View::ThisDir),
+        case(View::ThisDir, View::Combined),
+        case(View::Combined, View::Timeline),
+        case(View::Timeline, View::Sorted)
+    )]
+    fn toggle_view_with_directory_history(before: View, after: View) {
+        let mut state = State::new("");
+        state.directory_history = Some(Vec::new());
         state.view = before;
         state.toggle_view();
         assert_eq!(state.view, after);
     }
 
+    #[rstest()]
+    fn this_dir(fake_history: Vec<String>) {
+        let directory_history = vec![
+            (
+                String::from("/home/bwk/hstr-rs"),
+                String::from("cargo test"),
+            ),
+            (String::from("/home/bwk/hstr-rs"), String::from("make -j4")),
+            (
+                String::from("/home/bwk/hstr-rs"),
+                String::from("cargo test"),
+            ),
+            (
+                String::from("/home/bwk/hstr-rs"),
+                String::from("rm -rf target"),
+            ),
+            (String::from("/home/bwk"), String::from("lsusb")),
+        ];
+        assert_eq!(
+            super::this_dir(
+                Path::new("/home/bwk/hstr-rs"),
+                &fake_history,
+                Some(&directory_history)
+            ),
+            vec!["cargo test", "make -j4"]
+        );
+        assert!(super::this_dir(Path::new("/home/bwk"), &fake_history, None).is_empty());
+    }
+
+    #[rstest(
+        query,
+        expected,
+        case("gitpush", r"g\s*i\s*t\s*p\s*u\s*s\s*h"),
+        case(" a  b ", r"a\s*b"),
+        case("a.b", r"a\s*\.\s*b"),
+        case("", "")
+    )]
+    fn loose_pattern(query: &str, expected: &str) {
+        assert_eq!(super::loose_pattern(query), expected);
+    }
+
     #[rstest(
         before,
         after,
         case(SearchMode::Exact, SearchMode::Regex),
         case(SearchMode::Regex, SearchMode::Fuzzy),
-        case(SearchMode::Fuzzy, SearchMode::Exact)
+        case(SearchMode::Fuzzy, SearchMode::Loose),
+        case(SearchMode::Loose, SearchMode::Exact)
     )]
     fn toggle_search_mode(before: SearchMode, after: SearchMode) {
         let mut state = State::new("");
@@ -352,6 +1911,432 @@ mod tests {
         assert_eq!(state.search_mode, after);
     }
 
+    #[rstest(
+        before,
+        after,
+        case(SortMode::Frequency, SortMode::Recency),
+        case(SortMode::Recency, SortMode::Frecency),
+        case(SortMode::Frecency, SortMode::Alphabetical),
+        case(SortMode::Alphabetical, SortMode::Length),
+        case(SortMode::Length, SortMode::Frequency)
+    )]
+    fn toggle_sort_mode(before: SortMode, after: SortMode) {
+        let mut state = State::new("");
+        state.sort_mode = before;
+        state.toggle_sort_mode();
+        assert_eq!(state.sort_mode, after);
+    }
+
+    #[rstest(
+        command,
+        expected,
+        case("git push", 0),
+        case("sudo git push", 1),
+        case("/usr/bin/git status", 1),
+        case("legit", 2),
+        case("legit git", 1),
+        case("ls -la", u8::MAX)
+    )]
+    fn rank(command: &str, expected: u8) {
+        let search_regex = Regex::new("git").unwrap();
+        assert_eq!(super::rank(command, &search_regex), expected);
+    }
+
+    #[rstest(
+        search_mode,
+        expected,
+        case(SearchMode::Exact, vec!["git push", "git add .", "sudo git stash", "legit init"]),
+        case(SearchMode::Regex, vec!["legit init", "sudo git stash", "git push", "git add ."])
+    )]
+    fn search_ranks_prefix_matches(
+        search_mode: SearchMode,
+        expected: Vec<&str>,
+        mut fake_state: State,
+    ) {
+        let history: Vec<String> = [
+            "legit init",
+            "sudo git stash",
+            "git push",
+            "ls",
+            "git add .",
+        ]
+        .iter()
+        .map(|&x| x.into())
+        .collect();
+        fake_state.commands = Commands::new(&history, Vec::new());
+        fake_state.view = View::All;
+        fake_state.search_mode = search_mode;
+        fake_state.query = String::from("git");
+        fake_state.search();
+        assert_eq!(fake_state.commands(View::All), expected);
+    }
+
+    #[rstest(
+        command,
+        expected,
+        case("sudo reboot", true),
+        case("  doas pkg_add vim", true),
+        case("pkexec gparted", true),
+        case("sudo", true),
+        case("sudoku", false),
+        case("echo sudo", false),
+        case("", false)
+    )]
+    fn is_privileged(command: &str, expected: bool) {
+        assert_eq!(super::is_privileged(command), expected);
+    }
+
+    #[rstest(
+        range,
+        expected,
+        case("", Some((None, None))),
+        case("20-", Some((Some(20), None))),
+        case(" -10", Some((None, Some(10)))),
+        case("5-40", Some((Some(5), Some(40)))),
+        case("30", Some((Some(30), None))),
+        case("40-5", None),
+        case("long", None),
+        case("1-2-3", None)
+    )]
+    fn parse_length_range(range: &str, expected: Option<(Option<usize>, Option<usize>)>) {
+        assert_eq!(super::parse_length_range(range), expected);
+    }
+
+    #[rstest(
+        range,
+        query,
+        expected,
+        case("-5", "", vec!["lsusb", "lspci"]),
+        case(
+            "20-",
+            "git",
+            vec![
+                "git push origin master",
+                "git rebase -i HEAD~2",
+                "git checkout -b tests",
+                "nano .github/workflows/build.yml"
+            ]
+        ),
+        case("8-8", "", vec!["cat spam", "cat SPAM", "make -j4"])
+    )]
+    fn search_length_range(range: &str, query: &str, expected: Vec<&str>, mut fake_state: State) {
+        assert!(fake_state.set_length_range(range));
+        fake_state.query = String::from(query);
+        fake_state.search();
+        assert_eq!(fake_state.commands(fake_state.view), expected);
+    }
+
+    #[rstest()]
+    fn length_range(mut fake_state: State) {
+        assert_eq!(fake_state.length_range(), None);
+        fake_state.set_length_range(" 20 - ");
+        assert_eq!(fake_state.length_range().as_deref(), Some("20-"));
+        assert!(!fake_state.set_length_range("spam"));
+        assert_eq!(fake_state.length_range().as_deref(), Some("20-"));
+        fake_state.set_length_range("");
+        assert_eq!(fake_state.length_range(), None);
+    }
+
+    #[rstest()]
+    fn favorites_by_words() {
+        /* Removing one of two favorites with the same words keeps the other */
+        let history = vec![String::from("ls")];
+        let favorites = vec![String::from("make  -j4"), String::from("make -j4")];
+        let commands = Commands::new(&history, favorites);
+        let mut state = State::from_commands("", "", history, commands);
+        assert!(state.cmd_in_fav(" make -j4"));
+        state.add_or_rm_fav(String::from("make -j4"));
+        assert_eq!(state.to_restore.favorites, vec!["make -j4"]);
+        assert!(state.cmd_in_fav("make   -j4"));
+        state.add_or_rm_fav(String::from("make -j4"));
+        assert!(!state.cmd_in_fav("make -j4"));
+        state.add_or_rm_fav(String::from("ls  -la"));
+        assert!(state.cmd_in_fav("ls -la"));
+    }
+
+    #[rstest(
+        view,
+        reversed,
+        expected,
+        case(View::Sorted, false, vec!["make -j4", "ls -la", "cat SPAM", "cat spam"]),
+        case(View::Sorted, true, vec!["make -j4", "cat spam", "cat SPAM", "ls -la"]),
+        case(View::Timeline, false, vec!["make -j4", "cat spam", "cat SPAM", "ls -la"]),
+        case(View::Favorites, false, vec!["make  -j4"])
+    )]
+    fn pin_favorites(view: View, reversed: bool, expected: Vec<&str>) {
+        let history: Vec<String> = ["cat spam", "cat SPAM", "ls -la", "make -j4"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        let commands = Commands::new(&history, vec![String::from("make  -j4")]);
+        let mut state = State::from_commands("", "", history, commands);
+        state.pin_favorites = true;
+        state.reversed = reversed;
+        state.view = view;
+        state.search();
+        assert_eq!(state.commands(view), expected);
+    }
+
+    #[rstest()]
+    fn search_privileged_only(mut fake_state: State) {
+        fake_state.privileged_only = true;
+        fake_state.search();
+        assert_eq!(
+            fake_state.commands(fake_state.view),
+            vec!["sudo reboot", "sudo dkms add ."]
+        );
+    }
+
+    #[rstest(
+        dedupe,
+        expected,
+        case(false, vec!["git status", "git  status", "ls  -la", "ls -la ", "ls"]),
+        case(true, vec!["git status", "ls  -la", "ls"])
+    )]
+    fn toggle_dedupe(dedupe: bool, expected: Vec<&str>, mut fake_state: State) {
+        let history: Vec<String> = ["git status", "ls  -la", "git  status", "ls -la ", "ls"]
+            .iter()
+            .map(|&x| x.into())
+            .collect();
+        fake_state.commands.sorted = history.clone();
+        fake_state.to_restore.sorted = history;
+        fake_state.query = String::from("s");
+        fake_state.search_mode = SearchMode::Fuzzy;
+        if dedupe {
+            fake_state.toggle_dedupe();
+        }
+        fake_state.rerun_search();
+        assert_eq!(fake_state.commands(View::Sorted), expected);
+    }
+
+    #[rstest(
+        view,
+        expected,
+        case(View::Sorted, vec!["cat spam", "cat SPAM"]),
+        case(View::All, vec!["cat SPAM", "cat spam"])
+    )]
+    fn toggle_reversed(view: View, expected: Vec<&str>, mut fake_state: State) {
+        /* Only the sorted view is turned around */
+        fake_state.commands.sorted.reverse();
+        fake_state.commands.all.reverse();
+        fake_state.to_restore = fake_state.commands.clone();
+        fake_state.query = String::from("cat");
+        fake_state.view = view;
+        fake_state.toggle_reversed();
+        fake_state.rerun_search();
+        assert_eq!(fake_state.commands(view), expected);
+    }
+
+    #[rstest(privileged_only, case(true), case(false))]
+    fn toggle_privileged_only(privileged_only: bool, mut fake_state: State) {
+        fake_state.privileged_only = privileged_only;
+        fake_state.toggle_privileged_only();
+        assert_eq!(fake_state.privileged_only, !privileged_only);
+    }
+
+    #[rstest()]
+    fn toggle_case_with_query(mut fake_state: State) {
+        fake_state.query = String::from("SPAM");
+        fake_state.search();
+        assert_eq!(
+            fake_state.commands(fake_state.view),
+            vec!["cat spam", "cat SPAM", "grep -r spam ."]
+        );
+        fake_state.toggle_case();
+        fake_state.rerun_search();
+        assert_eq!(fake_state.commands(fake_state.view), vec!["cat SPAM"]);
+        fake_state.toggle_case();
+        fake_state.rerun_search();
+        assert_eq!(
+            fake_state.commands(fake_state.view),
+            vec!["cat spam", "cat SPAM", "grep -r spam ."]
+        );
+    }
+
+    #[rstest()]
+    fn toggle_search_mode_with_query(mut fake_state: State) {
+        fake_state.query = String::from("[0-9]+");
+        fake_state.search();
+        assert!(fake_state.commands(fake_state.view).is_empty());
+        fake_state.toggle_search_mode();
+        fake_state.rerun_search();
+        assert_eq!(
+            fake_state.commands(fake_state.view),
+            vec![
+                "git rebase -i HEAD~2",
+                "ping -c 10 www.google.com",
+                "xfce4-panel -r",
+                "make -j4"
+            ]
+        );
+    }
+
+    #[rstest(
+        query,
+        cursor,
+        expected,
+        expected_cursor,
+        case("git push origin", 15, "git push ", 9),
+        case("git push ", 9, "git ", 4),
+        case("git push   ", 11, "git ", 4),
+        case("cargo", 5, "", 0),
+        case("echo šampion čevapi", 19, "echo šampion ", 13),
+        case("", 0, "", 0),
+        case("git push origin", 8, "git  origin", 4),
+        case("git push origin", 6, "git sh origin", 4),
+        case("echo šampion čevapi", 12, "echo  čevapi", 5),
+        case("git push", 0, "git push", 0)
+    )]
+    fn delete_word(
+        query: &str,
+        cursor: usize,
+        expected: &str,
+        expected_cursor: usize,
+        mut fake_state: State,
+    ) {
+        fake_state.query = query.to_string();
+        assert_eq!(fake_state.delete_word(cursor), expected_cursor);
+        assert_eq!(fake_state.query, expected);
+    }
+
+    #[rstest()]
+    fn clear_query(mut fake_state: State) {
+        fake_state.query = String::from("git push");
+        fake_state.clear_query();
+        fake_state.rerun_search();
+        assert_eq!(fake_state.commands(View::Sorted), &fake_history()[..]);
+    }
+
+    #[rstest()]
+    fn remember_query(mut fake_state: State) {
+        [
+            "git push", "git pus", "git pu", "", "cargo", "carg", "git push",
+        ]
+        .iter()
+        .for_each(|query| {
+            fake_state.query = query.to_string();
+            fake_state.remember_query();
+        });
+        assert_eq!(fake_state.query_history, vec!["cargo", "git push"]);
+    }
+
+    #[rstest()]
+    fn recall_queries(mut fake_state: State) {
+        fake_state.query_history = vec![String::from("cat"), String::from("git")];
+        fake_state.query = String::from("spam");
+        fake_state.previous_query();
+        assert_eq!(fake_state.query, "git");
+        fake_state.previous_query();
+        assert_eq!(fake_state.query, "cat");
+        fake_state.previous_query();
+        assert_eq!(fake_state.query, "cat");
+        fake_state.next_query();
+        assert_eq!(fake_state.query, "git");
+        fake_state.next_query();
+        assert_eq!(fake_state.query, "spam");
+        fake_state.next_query();
+        assert_eq!(fake_state.query, "spam");
+        fake_state.rerun_search();
+        assert_eq!(
+            fake_state.commands(fake_state.view),
+            vec!["cat spam", "cat SPAM", "grep -r spam ."]
+        );
+    }
+
+    #[rstest()]
+    fn recall_queries_without_history(mut fake_state: State) {
+        fake_state.next_query();
+        fake_state.previous_query();
+        assert_eq!(fake_state.query, "");
+        assert_eq!(fake_state.query_recall, None);
+    }
+
+    #[test]
+    fn views() {
+        let names = View::iter().map(|view| view.as_ref().to_string());
+        assert_eq!(names.collect::<Vec<String>>(), VIEWS);
+    }
+
+    #[rstest(
+        favorites_enabled,
+        expected,
+        case(true, View::Favorites),
+        case(false, View::All)
+    )]
+    fn start_in(favorites_enabled: bool, expected: View, mut fake_state: State) {
+        fake_state.view = View::All;
+        fake_state.favorites_enabled = favorites_enabled;
+        fake_state.start_in(View::Favorites);
+        assert_eq!(fake_state.view, expected);
+    }
+
+    #[rstest()]
+    fn settings_roundtrip(mut fake_state: State) {
+        fake_state.search_mode = SearchMode::Fuzzy;
+        fake_state.case_sensitivity = true;
+        fake_state.reversed = true;
+        fake_state.sort_mode = SortMode::Recency;
+        fake_state.view = View::Favorites;
+        let settings = fake_state.settings();
+        assert_eq!(
+            settings,
+            vec![
+                "search_mode=fuzzy",
+                "case_sensitivity=true",
+                "reversed=true",
+                "sort_mode=recency",
+                "view=favorites"
+            ]
+        );
+        let mut state = State::new("");
+        state.apply_settings(&settings);
+        assert_eq!(state.search_mode, SearchMode::Fuzzy);
+        assert!(state.case_sensitivity);
+        assert!(state.reversed);
+        assert_eq!(state.sort_mode, SortMode::Recency);
+        assert_eq!(state.view, View::Favorites);
+    }
+
+    #[rstest(
+        settings,
+        case(vec!["search_mode=spam", "case_sensitivity=maybe", "view=nowhere"]),
+        case(vec!["search_mode", "spam=eggs", ""]),
+        case(vec!["view=this_dir"])
+    )]
+    fn apply_invalid_settings(settings: Vec<&str>, mut fake_state: State) {
+        fake_state.directory_history = None;
+        fake_state.apply_settings(&settings.iter().map(|&x| x.into()).collect::<Vec<String>>());
+        assert_eq!(fake_state.search_mode, SearchMode::Exact);
+        assert!(!fake_state.case_sensitivity);
+        assert_eq!(fake_state.view, View::Sorted);
+    }
+
+    #[rstest(
+        query,
+        search_mode,
+        expected,
+        case("git", SearchMode::Exact, 7),
+        case("Git", SearchMode::Exact, 0),
+        case("git", SearchMode::Fuzzy, 7),
+        case("Git", SearchMode::Fuzzy, 0)
+    )]
+    fn smart_case(query: &str, search_mode: SearchMode, expected: usize, mut fake_state: State) {
+        fake_state.smart_case = true;
+        fake_state.search_mode = search_mode;
+        fake_state.query = String::from(query);
+        fake_state.search();
+        assert_eq!(fake_state.commands(fake_state.view).len(), expected);
+    }
+
+    #[rstest(query, expected, case("git", true), case("Git", false))]
+    fn toggle_smart_case(query: &str, expected: bool, mut fake_state: State) {
+        fake_state.smart_case = true;
+        fake_state.query = String::from(query);
+        fake_state.toggle_case();
+        assert!(!fake_state.smart_case);
+        assert_eq!(fake_state.is_case_sensitive(), expected);
+    }
+
     #[rstest(case_sensitivity, case(true), case(false))]
     fn toggle_case(case_sensitivity: bool) {
         let mut state = State::new("");
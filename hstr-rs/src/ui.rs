@@ -1,7 +1,12 @@
-use crate::state::{SearchMode, State};
+use crate::favorites;
+use crate::sort;
+use crate::state::{self, SearchMode, State, View};
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use pp::*;
-use regex::Regex;
+use regex::{escape, Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::ops::Range;
+use strum_macros::EnumString;
 
 #[cfg(test)]
 use fake_ncurses as nc;
@@ -11,12 +16,95 @@ use ncurses as nc;
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const LABEL: &str =
-    "Type to filter, UP/DOWN move, ENTER/TAB select, DEL remove, ESC quit, C-f add/rm fav";
+    "Type to filter, UP/DOWN move, ENTER run, TAB paste, DEL remove, ESC quit, F1 help";
+
+/* With --no-execute, ENTER pastes like TAB does */
+const PASTE_LABEL: &str =
+    "Type to filter, UP/DOWN move, ENTER/TAB paste, DEL remove, ESC quit, F1 help";
+
+/* With --sticky, ENTER and TAB both copy, and hstr stays open */
+const STICKY_LABEL: &str =
+    "Type to filter, UP/DOWN move, ENTER/TAB copy, DEL remove, ESC quit, F1 help";
+
+/* The top bar and the status bar */
+const BAR_ROWS: i32 = 2;
+
+const READ_ONLY: &str = "History is read-only";
+
+const INVALID_LENGTH_RANGE: &str = "Not a length range, try 20-, -10 or 5-40";
+
+const FAVORITES_DISABLED: &str = "Favorites are disabled";
+
+const FAVORITES_UNAVAILABLE: &str = "Favorites file can't be read, using favorites in memory";
+
+const COPIED: &str = "Copied to the clipboard";
+
+const LOADING: &str = "Loading history...";
+
+const PREVIEW_LABEL: &str = "UP/DOWN scroll, any other key closes the preview";
+
+const HELP_LABEL: &str = "UP/DOWN scroll, any other key closes the help";
+
+/* Every key but ENTER and TAB, which depend on --no-execute and --sticky */
+const HELP: &[(&str, &str)] = &[
+    ("UP/DOWN", "move the selection"),
+    ("PGUP/PGDN", "turn the page"),
+    ("HOME/END", "select the first/last command"),
+    ("S-LEFT/S-RIGHT", "scroll a long command"),
+    ("LEFT/RIGHT", "move the cursor in the query"),
+    ("C-a/C-g", "cursor to the start/end of the query"),
+    ("C-w", "delete the word before the cursor"),
+    ("C-u", "clear the query"),
+    ("C-p/C-n", "previous/next query"),
+    ("C-/", "next view"),
+    ("C-e", "next search mode"),
+    ("C-t", "toggle case sensitivity"),
+    ("C-r", "reverse the sorted view"),
+    ("C-]", "next sort strategy"),
+    ("C-b", "toggle unique commands"),
+    ("C-x", "toggle privileged commands only"),
+    ("F4", "show only commands of some length"),
+    ("C-l", "toggle wrapping long commands"),
+    ("C-o", "preview the command"),
+    ("F5", "edit the command in $EDITOR"),
+    ("C-f", "add/remove the command to/from favorites"),
+    ("C-v", "tag the command"),
+    ("F2", "write a note on the command"),
+    ("F3", "show timestamps: relative, absolute, hidden"),
+    ("DEL", "delete the command from history"),
+    ("S-DEL", "delete only this occurrence"),
+    ("C-k", "delete all matching commands"),
+    ("C-y", "undo the last deletion"),
+    ("F1", "show this help"),
+    ("ESC", "quit"),
+];
+
+/* Between the command and the timestamp column */
+const TIME_GAP: usize = 2;
+
+/* Put in front of favorites, so they stand out without color */
+const FAVORITE_MARKER: &str = "\u{2605} ";
 
 pub struct UserInterface {
     pub page: i32,
     pub selected: i32,
     pub cursor: Cursor,
+    pub offset: usize,
+    pub show_help: bool,
+    pub margin: i32,
+    pub max_rows: Option<usize>,
+    pub timestamps: Option<TimeFormat>,
+    pub navigation: Navigation,
+    pub vim_mode: Option<VimMode>,
+    pub status_format: Option<String>,
+    pub prompt_format: Option<String>,
+    pub editing: Option<String>,
+    pub redacted: Vec<Regex>,
+    pub mask: char,
+    pub positions: HashMap<View, (i32, i32)>,
+    pub wrap: bool,
+    pub enter_executes: bool,
+    pub sticky: bool,
 }
 
 impl UserInterface {
@@ -25,6 +113,41 @@ impl UserInterface {
             page: 1,
             selected: 0,
             cursor: Cursor::new(query),
+            offset: 0,
+            show_help: true,
+            margin: 0,
+            max_rows: None,
+            timestamps: None,
+            navigation: Navigation::Paged,
+            vim_mode: None,
+            status_format: None,
+            prompt_format: None,
+            editing: None,
+            redacted: Vec::new(),
+            mask: '*',
+            positions: HashMap::new(),
+            wrap: false,
+            enter_executes: true,
+            sticky: false,
+        }
+    }
+
+    fn reserved_rows(&self) -> i32 {
+        /* The bars are always there,
+         * the help label only if it is enabled
+         */
+        BAR_ROWS + self.show_help as i32
+    }
+
+    fn rows_per_page(&self) -> usize {
+        /* The margin is left blank below the commands, and so are
+         * the rows past --max-rows; whatever they are set to,
+         * a page still holds at least one command
+         */
+        let rows = usable(nc::LINES() - self.reserved_rows() - self.margin);
+        match self.max_rows {
+            Some(max_rows) => rows.min(max_rows.max(1)),
+            None => rows,
         }
     }
 
@@ -38,11 +161,31 @@ impl UserInterface {
             .cloned()
     }
 
+    pub fn selected_position(&self, state: &State) -> Option<usize> {
+        /* Where the selected command is in the whole view */
+        let first = if self.wrap {
+            self.wrapped_pages(state, self.page as usize)
+                .get(self.page as usize - 1)?
+                .start
+        } else {
+            (self.page as usize - 1) * self.rows_per_page()
+        };
+        self.selected(state)?;
+        Some(first + self.selected as usize)
+    }
+
     fn page_contents(&self, state: &State) -> Vec<String> {
         let current_view = state.view;
         let commands = state.commands(current_view);
+        if self.wrap {
+            let pages = self.wrapped_pages(state, self.page as usize);
+            return match pages.get(self.page as usize - 1) {
+                Some(range) => commands[range.clone()].to_vec(),
+                None => Vec::new(),
+            };
+        }
         match commands
-            .chunks(nc::LINES() as usize - 3)
+            .chunks(self.rows_per_page())
             .nth(self.page as usize - 1)
         {
             Some(cmds) => cmds.to_vec(),
@@ -50,80 +193,295 @@ impl UserInterface {
         }
     }
 
+    fn wrapped_pages(&self, state: &State, last: usize) -> Vec<Range<usize>> {
+        /* With wrapping, a page takes commands until their rows
+         * fill it up; a command taller than a page gets one for
+         * itself, and is cut off at the bottom. Wrapping every
+         * command is slow, so it stops at the `last` page.
+         */
+        let rows = self.rows_per_page();
+        let commands = state.commands(state.view);
+        let mut pages = Vec::new();
+        let mut start = 0;
+        let mut used = 0;
+        for (idx, cmd) in commands.iter().enumerate() {
+            let height = self.row_height(cmd, state.cmd_in_fav(cmd)).min(rows);
+            if used + height > rows {
+                pages.push(start..idx);
+                if pages.len() == last {
+                    return pages;
+                }
+                start = idx;
+                used = 0;
+            }
+            used += height;
+        }
+        if start < commands.len() {
+            pages.push(start..commands.len());
+        }
+        pages
+    }
+
+    fn row_height(&self, entry: &str, favorite: bool) -> usize {
+        wrap(
+            &self.rendered(entry, favorite),
+            self.command_width(favorite),
+        )
+        .len()
+    }
+
+    fn rendered(&self, entry: &str, favorite: bool) -> String {
+        let entry = if favorite {
+            favorites::assemble(entry, " && ")
+        } else {
+            entry.to_string()
+        };
+        redact(&single_line(&entry), &self.redacted, self.mask)
+    }
+
+    fn time_column(&self) -> usize {
+        /* Left out when it would take half of the row */
+        self.timestamps
+            .map(|format| format.width() + TIME_GAP)
+            .filter(|&column| usable(nc::COLS() - 2) >= 2 * column)
+            .unwrap_or(0)
+    }
+
+    fn command_width(&self, favorite: bool) -> usize {
+        text_width(favorite)
+            .saturating_sub(self.time_column())
+            .max(1)
+    }
+
+    pub fn toggle_timestamps(&mut self) {
+        self.timestamps = match self.timestamps {
+            None => Some(TimeFormat::Relative),
+            Some(TimeFormat::Relative) => Some(TimeFormat::Absolute),
+            Some(TimeFormat::Absolute) => None,
+        };
+    }
+
     pub fn populate_screen(&self, state: &State) {
-        let matcher = SkimMatcherV2::default();
+        /* Matched chars are found with the case setting the search used */
+        let case_sensitive = state.is_case_sensitive();
+        let matcher = if case_sensitive {
+            SkimMatcherV2::default().respect_case()
+        } else {
+            SkimMatcherV2::default()
+        };
+        let last_used = if self.time_column() > 0 {
+            state.last_used()
+        } else {
+            HashMap::new()
+        };
+        let now = sort::now();
+        let mut row = self.reserved_rows();
         self.page_contents(state)
             .iter()
             .enumerate()
-            .for_each(|(row_idx, cmd)| {
+            .for_each(|(idx, entry)| {
                 /* Print everything normally first; then
                  * Paint matched chars, if any; then
-                 * Paint favorite, if any; then
+                 * Paint favorite or favorite sequence, if any; then
                  * Finally, paint selection
                  */
-                let cmd = &cmd
-                    .chars()
-                    .take(nc::COLS() as usize - 2)
-                    .collect::<String>();
-                nc::mvaddstr(row_idx as i32 + 3, 1, &ljust(cmd));
-                match state.search_mode {
-                    SearchMode::Exact | SearchMode::Regex => {
-                        let matches = self.substring_indices(cmd, &state.query);
-                        if !matches.is_empty() {
-                            self.paint_matched_chars(cmd, matches, row_idx);
+                let selected = idx == self.selected as usize;
+                let favorite = state.cmd_in_fav(entry);
+                let width = self.command_width(favorite);
+                let lines = if self.wrap {
+                    wrap(&self.rendered(entry, favorite), width)
+                        .into_iter()
+                        .take(self.rows_per_page())
+                        .collect()
+                } else {
+                    let offset = if selected { self.offset } else { 0 };
+                    vec![self
+                        .rendered(entry, favorite)
+                        .chars()
+                        .skip(offset)
+                        .take(width)
+                        .collect::<String>()]
+                };
+                let cmd = lines.concat();
+                let matches = match state.search_mode {
+                    SearchMode::Exact | SearchMode::Regex | SearchMode::Loose => self
+                        .substring_indices(&cmd, &state.query, state.search_mode, case_sensitive),
+                    SearchMode::Fuzzy => matcher
+                        .fuzzy_indices(&cmd, &state.query)
+                        .map(|m| m.1)
+                        .unwrap_or_default(),
+                };
+                let mut line_start = 0;
+                lines.iter().enumerate().for_each(|(line_idx, line)| {
+                    let gutter = gutter(favorite, line_idx);
+                    let line_matches = matches
+                        .iter()
+                        .filter(|&&i| i >= line_start && i < line_start + line.len())
+                        .map(|i| i - line_start + gutter.len())
+                        .collect::<Vec<usize>>();
+                    let line_start_next = line_start + line.len();
+                    let line = gutter + line;
+                    nc::mvaddstr(row, 1, &ljust(&line));
+                    if !line_matches.is_empty() {
+                        self.paint_matched_chars(&line, line_matches, row);
+                    }
+                    if favorite {
+                        if favorites::is_sequence(entry) {
+                            self.paint_sequence(&line, row);
+                        } else {
+                            self.paint_favorite(&line, row);
                         }
                     }
-                    SearchMode::Fuzzy => {
-                        if let Some(matches) = matcher.fuzzy_indices(cmd, &state.query) {
-                            self.paint_matched_chars(cmd, matches.1, row_idx);
+                    if selected {
+                        self.paint_selected(&line, row);
+                    }
+                    if line_idx == lines.len() - 1 {
+                        if let Some(note) = state.note_of(entry) {
+                            self.paint_note(note, &line, row, selected);
                         }
                     }
-                }
-                if state.cmd_in_fav(cmd) {
-                    self.paint_favorite(cmd.clone(), row_idx);
-                }
-                self.paint_selected(cmd, row_idx);
+                    if let (0, Some(format), Some(&timestamp)) =
+                        (line_idx, self.timestamps, last_used.get(entry.as_str()))
+                    {
+                        self.paint_time(&time_text(format, timestamp, now), row, selected);
+                    }
+                    line_start = line_start_next;
+                    row += 1;
+                });
             });
+        if state.commands(state.view).is_empty() {
+            self.paint_empty_message(&state.query);
+        }
         self.paint_bars(state);
     }
 
-    fn substring_indices<'b>(&self, string: &'b str, substring: &'b str) -> Vec<usize> {
-        match Regex::new(substring) {
+    fn substring_indices(
+        &self,
+        string: &str,
+        substring: &str,
+        search_mode: SearchMode,
+        case_sensitive: bool,
+    ) -> Vec<usize> {
+        /* Highlight what the search filtered on, so only
+         * a regex query is taken as a regex
+         */
+        let pattern = match search_mode {
+            SearchMode::Exact => escape(substring),
+            SearchMode::Loose => state::loose_pattern(substring),
+            _ => substring.to_string(),
+        };
+        match RegexBuilder::new(&pattern)
+            .case_insensitive(!case_sensitive)
+            .build()
+        {
             Ok(r) => r.find_iter(string).flat_map(|m| m.range()).collect(),
             Err(_) => vec![],
         }
     }
 
-    fn paint_matched_chars(&self, command: &str, indices: Vec<usize>, row_idx: usize) {
+    fn paint_matched_chars(&self, command: &str, indices: Vec<usize>, row: i32) {
         column_indices(command).for_each(|(col_idx, byte_idx, ch)| {
             if indices.contains(&byte_idx) {
-                nc::attron(nc::COLOR_PAIR(5) | nc::A_BOLD());
-                nc::mvaddstr(row_idx as i32 + 3, col_idx as i32 + 1, &ch.to_string());
-                nc::attroff(nc::COLOR_PAIR(5) | nc::A_BOLD());
+                nc::attron(style(5) | nc::A_BOLD());
+                nc::mvaddstr(row, col_idx as i32 + 1, &ch.to_string());
+                nc::attroff(style(5) | nc::A_BOLD());
             }
         });
     }
 
-    fn paint_favorite(&self, entry: String, index: usize) {
-        nc::attron(nc::COLOR_PAIR(4));
-        nc::mvaddstr(index as i32 + 3, 1, &ljust(&entry));
-        nc::attroff(nc::COLOR_PAIR(4));
+    fn paint_favorite(&self, entry: &str, row: i32) {
+        nc::attron(style(4));
+        nc::mvaddstr(row, 1, &ljust(entry));
+        nc::attroff(style(4));
+    }
+
+    fn paint_sequence(&self, entry: &str, row: i32) {
+        nc::attron(style(7));
+        nc::mvaddstr(row, 1, &ljust(entry));
+        nc::attroff(style(7));
+    }
+
+    fn paint_selected(&self, entry: &str, row: i32) {
+        nc::attron(style(2));
+        nc::mvaddstr(row, 1, &ljust(entry));
+        nc::attroff(style(2));
+    }
+
+    fn paint_note(&self, note: &str, line: &str, row: i32, selected: bool) {
+        let room = usable(nc::COLS() - 2)
+            .saturating_sub(line.width())
+            .saturating_sub(self.time_column());
+        if let Some(text) = note_text(note, room) {
+            let attrs = if selected {
+                style(2) | nc::A_DIM()
+            } else {
+                nc::A_DIM()
+            };
+            nc::attron(attrs);
+            nc::mvaddstr(row, 1 + line.width() as i32, &text);
+            nc::attroff(attrs);
+        }
+    }
+
+    fn paint_time(&self, text: &str, row: i32, selected: bool) {
+        /* Right-aligned at the edge, clear of the command */
+        let column = self.time_column();
+        if column == 0 {
+            return;
+        }
+        let attrs = if selected {
+            style(2) | nc::A_DIM()
+        } else {
+            nc::A_DIM()
+        };
+        let start = usable(nc::COLS() - 2) - column + 1;
+        nc::attron(attrs);
+        nc::mvaddstr(row, start as i32, &format!("{:>1$}", text, column));
+        nc::attroff(attrs);
+    }
+
+    fn paint_empty_message(&self, query: &str) {
+        self.paint_centered(empty_message(query));
+    }
+
+    pub fn notify_loading(&self) {
+        self.paint_centered(LOADING);
+        nc::refresh();
+    }
+
+    fn paint_centered(&self, message: &str) {
+        let row = self.reserved_rows() + self.rows_per_page() as i32 / 2;
+        let column = (nc::COLS() - message.width() as i32) / 2;
+        nc::mvaddstr(row, column.max(1), message);
     }
 
-    fn paint_selected(&self, entry: &str, index: usize) {
-        if index == self.selected as usize {
-            nc::attron(nc::COLOR_PAIR(2));
-            nc::mvaddstr(index as i32 + 3, 1, &ljust(&entry));
-            nc::attroff(nc::COLOR_PAIR(2));
+    fn label(&self) -> &'static str {
+        if self.sticky {
+            STICKY_LABEL
+        } else if self.enter_executes {
+            LABEL
+        } else {
+            PASTE_LABEL
         }
     }
 
     fn paint_bars(&self, state: &State) {
-        nc::mvaddstr(1, 1, LABEL);
-        nc::attron(nc::COLOR_PAIR(3));
-        nc::mvaddstr(2, 1, &ljust(&status_bar(&state, self)));
-        nc::attroff(nc::COLOR_PAIR(3));
-        nc::mvaddstr(0, 1, &top_bar(&state.query));
+        if self.show_help {
+            nc::mvaddstr(1, 1, &ljust(self.label()));
+        }
+        let status_color = if state.invalid_regex { 6 } else { 3 };
+        nc::attron(style(status_color));
+        nc::mvaddstr(
+            self.reserved_rows() - 1,
+            1,
+            &ljust(&status_bar(state, self)),
+        );
+        nc::attroff(style(status_color));
+        nc::mvaddstr(0, 1, &top_bar(&self.prompt(), &state.query));
+        /* Painting leaves the cursor at the end of the top bar,
+         * so it is put back where it is in the query
+         */
+        self.place_cursor();
     }
 
     pub fn turn_page(&mut self, state: &State, direction: Direction) {
@@ -156,57 +514,308 @@ impl UserInterface {
          * on page 1.
          */
         nc::clear();
+        self.offset = 0;
         let next_page = self.page - 1 + direction as i32;
         let pages = self.total_pages(state);
         self.page = match i32::checked_rem_euclid(next_page, pages) {
             Some(x) => x + 1,
             None => 1,
-        }
+        };
+        /* The last page is usually shorter than the others,
+         * so the selection has to stay within its commands
+         */
+        self.selected = self.selected.min(self.page_size(state) - 1).max(0);
     }
 
     pub fn total_pages(&self, state: &State) -> i32 {
+        if self.wrap {
+            return self.wrapped_pages(state, usize::MAX).len() as i32;
+        }
         let current_view = state.view;
         let commands = state.commands(current_view);
-        commands.chunks(nc::LINES() as usize - 3).len() as i32
+        commands.chunks(self.rows_per_page()).len() as i32
+    }
+
+    pub fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        self.select_first();
     }
 
     pub fn move_selected(&mut self, state: &State, direction: Direction) {
         let page_size = self.page_size(state);
-        self.selected += direction as i32;
-        if let Some(wraparound) = i32::checked_rem_euclid(self.selected, page_size) {
-            self.selected = wraparound;
-            match direction {
-                Direction::Forward => {
-                    if self.selected == 0 {
-                        self.turn_page(state, Direction::Forward);
+        self.offset = 0;
+        match self.navigation {
+            Navigation::Paged => {
+                self.selected += direction as i32;
+                if let Some(wraparound) = i32::checked_rem_euclid(self.selected, page_size) {
+                    self.selected = wraparound;
+                    match direction {
+                        Direction::Forward => {
+                            if self.selected == 0 {
+                                self.turn_page(state, Direction::Forward);
+                            }
+                        }
+                        Direction::Backward => {
+                            if self.selected == (page_size - 1) {
+                                self.turn_page(state, Direction::Backward);
+                                self.selected = self.page_size(state) - 1;
+                            }
+                        }
                     }
                 }
-                Direction::Backward => {
-                    if self.selected == (page_size - 1) {
-                        self.turn_page(state, Direction::Backward);
-                        self.selected = self.page_size(state) - 1;
-                    }
+            }
+            Navigation::Stay => {
+                let next = self.selected + direction as i32;
+                if (0..page_size).contains(&next) {
+                    self.selected = next;
+                }
+            }
+            Navigation::WrapInPage => {
+                let next = self.selected + direction as i32;
+                if let Some(wraparound) = i32::checked_rem_euclid(next, page_size) {
+                    self.selected = wraparound;
                 }
             }
         }
     }
 
+    pub fn select_first(&mut self) {
+        /* The list has changed, so the places
+         * remembered in the other views are gone
+         */
+        nc::clear();
+        self.page = 1;
+        self.selected = 0;
+        self.offset = 0;
+        self.positions.clear();
+    }
+
+    pub fn toggle_view(&mut self, state: &mut State) {
+        /* Every view keeps its own page and selection */
+        self.positions
+            .insert(state.view, (self.page, self.selected));
+        state.toggle_view();
+        let (page, selected) = self.positions.get(&state.view).copied().unwrap_or((1, 0));
+        nc::clear();
+        self.page = page;
+        self.selected = selected;
+        self.offset = 0;
+        self.clamp_selection(state);
+    }
+
+    pub fn select_last(&mut self, state: &State) {
+        nc::clear();
+        self.page = self.total_pages(state).max(1);
+        self.selected = (self.page_size(state) - 1).max(0);
+        self.offset = 0;
+    }
+
+    pub fn vim_key(&mut self, state: &State, ch: u32) {
+        /* In normal mode, letters navigate instead of being typed,
+         * and whatever isn't bound to anything is ignored
+         */
+        match std::char::from_u32(ch) {
+            Some('j') => self.move_selected(state, Direction::Forward),
+            Some('k') => self.move_selected(state, Direction::Backward),
+            Some('l') => self.turn_page(state, Direction::Forward),
+            Some('h') => self.turn_page(state, Direction::Backward),
+            Some('/') => self.vim_mode = Some(VimMode::Insert),
+            _ => {}
+        }
+    }
+
+    pub fn start_editing(&mut self, state: &mut State, command: &str) {
+        /* The command takes the place of the query, which
+         * is kept aside to be restored if editing is cancelled
+         */
+        self.editing = Some(std::mem::replace(&mut state.query, command.to_string()));
+        self.select_first();
+    }
+
+    pub fn stop_editing(&mut self, state: &mut State) {
+        if let Some(query) = self.editing.take() {
+            state.query = query;
+            self.select_first();
+        }
+    }
+
+    pub fn select_row(&mut self, state: &State, row: i32) {
+        if (0..self.page_size(state)).contains(&row) {
+            self.selected = row;
+            self.offset = 0;
+        }
+    }
+
+    pub fn scroll_selected(&mut self, state: &State, direction: Direction) {
+        /* Only the selected row scrolls, and never so far
+         * that the end of the command leaves the right edge;
+         * wrapped commands are shown whole, so they don't
+         */
+        if self.wrap {
+            return;
+        }
+        if let Some(command) = self.selected(state) {
+            let length = self
+                .rendered(&command, state.cmd_in_fav(&command))
+                .chars()
+                .count();
+            let max_offset = length.saturating_sub(self.command_width(false));
+            self.offset = match direction {
+                Direction::Forward => (self.offset + 1).min(max_offset),
+                Direction::Backward => self.offset.saturating_sub(1),
+            };
+        }
+    }
+
+    pub fn clamp_selection(&mut self, state: &State) {
+        /* After the terminal shrinks, pages hold fewer commands,
+         * so the page and the selection may point past the end
+         */
+        self.page = self.page.min(self.total_pages(state)).max(1);
+        self.selected = self.selected.min(self.page_size(state) - 1).max(0);
+    }
+
     pub fn retain_selected(&mut self, state: &State) {
         let page_size = self.page_size(state);
-        if self.selected == page_size - 1 {
+        if self.selected > 0 && self.selected == page_size - 1 {
             self.selected -= 1;
         }
+        self.offset = 0;
+    }
+
+    pub fn ask_before_deletion(&self, command: &str, all_occurrences: bool) {
+        self.paint_prompt(&deletion_prompt(&single_line(command), all_occurrences));
+    }
+
+    pub fn ask_for_tags(&self, command: &str, tags: &str) -> Option<String> {
+        self.ask(tags, |typed| tags_prompt(&single_line(command), typed))
+    }
+
+    pub fn ask_for_note(&self, command: &str, note: &str) -> Option<String> {
+        self.ask(note, |typed| note_prompt(&single_line(command), typed))
+    }
+
+    pub fn ask_for_length_range(&self, range: &str) -> Option<String> {
+        self.ask(range, length_range_prompt)
+    }
+
+    fn ask(&self, initial: &str, prompt: impl Fn(&str) -> String) -> Option<String> {
+        /* ENTER keeps what is typed on the prompt line, ESC drops it */
+        let mut typed = initial.to_string();
+        loop {
+            self.paint_prompt(&prompt(&typed));
+            if let Some(done) = edit_line(&mut typed, nc::getch()) {
+                return if done { Some(typed) } else { None };
+            }
+        }
+    }
+
+    pub fn ask_before_batch_deletion(&self, count: usize) {
+        self.paint_prompt(&batch_deletion_prompt(count));
+    }
+
+    pub fn notify_read_only(&self) {
+        self.paint_prompt(READ_ONLY);
+    }
+
+    pub fn notify_invalid_length_range(&self) {
+        self.paint_prompt(INVALID_LENGTH_RANGE);
+    }
+
+    pub fn notify_favorites_disabled(&self) {
+        self.paint_prompt(FAVORITES_DISABLED);
+    }
+
+    pub fn notify_favorites_unavailable(&self) {
+        self.paint_prompt(FAVORITES_UNAVAILABLE);
+    }
+
+    pub fn notify_copied(&self) {
+        self.paint_prompt(COPIED);
+    }
+
+    pub fn notify_copy_failed(&self, error: &std::io::Error) {
+        self.paint_prompt(&error.to_string());
+    }
+
+    pub fn notify_editor_failed(&self, error: &std::io::Error) {
+        self.paint_prompt(&format!("Could not run the editor: {}", error));
     }
 
-    pub fn ask_before_deletion(&self, command: &str) {
+    fn paint_prompt(&self, prompt: &str) {
         nc::mvaddstr(1, 0, &format!("{1:0$}", nc::COLS() as usize, ""));
-        nc::attron(nc::COLOR_PAIR(6));
-        nc::mvaddstr(1, 1, &deletion_prompt(command));
-        nc::attroff(nc::COLOR_PAIR(6));
+        nc::attron(style(6));
+        nc::mvaddstr(1, 1, prompt);
+        nc::attroff(style(6));
+    }
+
+    pub fn preview(&self, command: &str) {
+        self.overlay(PREVIEW_LABEL, || wrap(command, usable(nc::COLS() - 2)));
+    }
+
+    pub fn help(&self) {
+        let lines = self.help_lines();
+        self.overlay(HELP_LABEL, || lines.clone());
+    }
+
+    fn help_lines(&self) -> Vec<String> {
+        let (enter, tab) = if self.sticky {
+            (
+                "copy the command, like TAB",
+                "copy the command and keep going",
+            )
+        } else if self.enter_executes {
+            ("run the command", "paste the command, to edit it first")
+        } else {
+            (
+                "paste the command, like TAB",
+                "paste the command, to edit it first",
+            )
+        };
+        [("ENTER", enter), ("TAB", tab)]
+            .iter()
+            .copied()
+            .chain(HELP.iter().copied())
+            .map(|(key, action)| format!("{:<16}{}", key, action))
+            .collect()
+    }
+
+    fn overlay(&self, label: &str, lines: impl Fn() -> Vec<String>) {
+        /* Takes over the whole screen until a key other than
+         * UP/DOWN is pressed, rewrapping on every resize
+         */
+        let mut scroll = 0;
+        loop {
+            let lines = lines();
+            let rows = usable(nc::LINES() - 1);
+            scroll = clamp_scroll(scroll, lines.len(), rows);
+            nc::clear();
+            nc::attron(style(3));
+            nc::mvaddstr(0, 1, &ljust(label));
+            nc::attroff(style(3));
+            lines
+                .iter()
+                .skip(scroll)
+                .take(rows)
+                .enumerate()
+                .for_each(|(row_idx, line)| {
+                    nc::mvaddstr(row_idx as i32 + 1, 1, line);
+                });
+            match nc::getch() {
+                nc::KEY_UP => scroll = scroll.saturating_sub(1),
+                nc::KEY_DOWN => scroll += 1,
+                nc::KEY_RESIZE => continue,
+                _ => break,
+            }
+        }
+        nc::clear();
+    }
+
+    pub fn confirm(&self) -> bool {
+        answer(std::iter::repeat_with(nc::getch))
     }
 
     pub fn move_cursor(&mut self, state: &mut State, direction: Direction) {
-        let prompt_length = pp::get_shell_prompt().chars().count();
         match direction {
             Direction::Backward => {
                 self.cursor.chars_moved = self.cursor.chars_moved.saturating_sub(1);
@@ -219,11 +828,7 @@ impl UserInterface {
                 )
                 .iter()
                 .sum();
-                nc::wmove(
-                    nc::stdscr(),
-                    0,
-                    prompt_length as i32 + 2 + self.cursor.column as i32,
-                );
+                self.place_cursor();
             }
             Direction::Forward => {
                 if self.cursor.column < state.query.width() {
@@ -237,23 +842,46 @@ impl UserInterface {
                     )
                     .iter()
                     .sum();
-                    nc::wmove(
-                        nc::stdscr(),
-                        0,
-                        prompt_length as i32 + 2 + self.cursor.column as i32,
-                    );
+                    self.place_cursor();
                 }
             }
         }
     }
 
-    pub fn insert_char_in_query(&self, state: &mut State, ch: char) {
+    fn prompt(&self) -> String {
+        pp::get_shell_prompt(self.prompt_format.as_deref())
+    }
+
+    fn place_cursor(&self) {
+        let prompt_length = self.prompt().chars().count();
+        nc::wmove(
+            nc::stdscr(),
+            0,
+            prompt_length as i32 + 2 + self.cursor.column as i32,
+        );
+    }
+
+    pub fn cursor_to_start(&mut self, state: &State) {
+        self.cursor = Cursor::new(&state.query);
+        self.place_cursor();
+    }
+
+    pub fn cursor_to_end(&mut self, state: &mut State) {
+        self.cursor = Cursor::new(&state.query);
+        state
+            .query
+            .clone()
+            .chars()
+            .for_each(|_| self.move_cursor(state, Direction::Forward));
+    }
+
+    pub fn insert_in_query(&self, state: &mut State, text: &str) {
         let query_length_in_bytes = state
             .query
             .chars()
             .take(self.cursor.chars_moved)
             .fold(0, |acc, x| acc + x.to_string().len());
-        state.query.insert(query_length_in_bytes, ch);
+        state.query.insert_str(query_length_in_bytes, text);
     }
 
     pub fn remove_char_from_query(&self, string: &str) -> String {
@@ -310,16 +938,129 @@ pub fn column_indices(s: &str) -> ColumnIndices {
 
 pub mod curses {
     use ncurses as nc;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    const SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+    /* The last signal caught, or 0 */
+    static SIGNALLED: AtomicI32 = AtomicI32::new(0);
+
+    /* Curses is torn down when this goes out of scope, so returning
+     * early on an error or unwinding from a panic restores the terminal
+     */
+    pub struct Session {
+        previous: Vec<(libc::c_int, libc::sigaction)>,
+    }
+
+    impl Session {
+        pub fn start(on_tty: bool) -> Self {
+            /* Caught before curses starts, which then leaves them be */
+            let previous = catch_signals();
+            if on_tty {
+                init_on_tty();
+            } else {
+                init();
+            }
+            /* The panic message would be printed on the curses screen,
+             * and wiped with it, so the terminal is restored first
+             */
+            let default_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                teardown();
+                default_hook(info);
+            }));
+            Session { previous }
+        }
+    }
+
+    impl Drop for Session {
+        fn drop(&mut self) {
+            teardown();
+            self.previous.iter().for_each(|(signal, action)| unsafe {
+                libc::sigaction(*signal, action, std::ptr::null_mut());
+            });
+        }
+    }
+
+    fn catch_signals() -> Vec<(libc::c_int, libc::sigaction)> {
+        /* Ctrl-C, a kill or a closed terminal only set a flag, as
+         * curses can't be torn down safely from a signal handler.
+         * Without SA_RESTART, the read waiting for a key fails, so
+         * the main loop sees it and quits the normal way. A second
+         * signal, should that get stuck, ends the process right away.
+         */
+        extern "C" fn catch(signal: libc::c_int) {
+            if SIGNALLED.swap(signal, Ordering::SeqCst) != 0 {
+                unsafe {
+                    libc::signal(signal, libc::SIG_DFL);
+                    libc::raise(signal);
+                }
+            }
+        }
+        let handler: extern "C" fn(libc::c_int) = catch;
+        SIGNALS
+            .iter()
+            .filter_map(|&signal| unsafe {
+                /* Signals ignored already, e.g. SIGHUP under nohup, stay so */
+                let mut previous: libc::sigaction = std::mem::zeroed();
+                libc::sigaction(signal, std::ptr::null(), &mut previous);
+                if previous.sa_sigaction == libc::SIG_IGN {
+                    return None;
+                }
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handler as libc::sighandler_t;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+                Some((signal, previous))
+            })
+            .collect()
+    }
+
+    pub fn signalled() -> Option<i32> {
+        match SIGNALLED.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+
+    pub fn exit_if_signalled() {
+        /* Once the session is over and the handlers are back, the
+         * signal is raised again, so the shell sees how hstr-rs ended
+         */
+        if let Some(signal) = signalled() {
+            unsafe { libc::raise(signal) };
+            std::process::exit(128 + signal);
+        }
+    }
 
     pub fn init() {
         nc::setlocale(nc::LcCategory::all, "");
         nc::initscr();
+        setup();
+    }
+
+    pub fn init_on_tty() {
+        /* Draws on the terminal even when stdout is piped */
+        nc::setlocale(nc::LcCategory::all, "");
+        let tty = unsafe { libc::fopen("/dev/tty\0".as_ptr() as _, "r+\0".as_ptr() as _) };
+        if tty.is_null() {
+            nc::initscr();
+        } else {
+            nc::newterm(None, tty, tty);
+        }
+        setup();
+    }
+
+    fn setup() {
         nc::noecho();
         nc::keypad(nc::stdscr(), true);
         init_color_pairs();
     }
 
     pub fn init_color_pairs() {
+        if !nc::has_colors() {
+            return;
+        }
         nc::start_color();
         nc::init_pair(1, nc::COLOR_WHITE, nc::COLOR_BLACK); // normal
         nc::init_pair(2, nc::COLOR_WHITE, nc::COLOR_GREEN); // highlighted-green (selected item)
@@ -327,9 +1068,43 @@ pub mod curses {
         nc::init_pair(4, nc::COLOR_CYAN, nc::COLOR_BLACK); // white (favorites)
         nc::init_pair(5, nc::COLOR_RED, nc::COLOR_BLACK); // red (searched items)
         nc::init_pair(6, nc::COLOR_WHITE, nc::COLOR_RED); // higlighted-red
+        nc::init_pair(7, nc::COLOR_MAGENTA, nc::COLOR_BLACK); // magenta (favorite sequences)
+    }
+
+    pub fn typed_ahead() -> String {
+        /* Pasting or typing fast sends characters within a few
+         * milliseconds of each other. Taking them in one go spares a
+         * search per keystroke; the first key that isn't part of the
+         * query is put back for the main loop
+         */
+        const DEBOUNCE_MS: i32 = 15;
+        let mut typed = String::new();
+        nc::timeout(DEBOUNCE_MS);
+        loop {
+            match nc::get_wch() {
+                Some(nc::WchResult::Char(ch)) => match std::char::from_u32(ch) {
+                    Some(ch) if !ch.is_control() => typed.push(ch),
+                    _ => {
+                        nc::unget_wch(ch);
+                        break;
+                    }
+                },
+                Some(nc::WchResult::KeyCode(code)) => {
+                    nc::ungetch(code);
+                    break;
+                }
+                None => break,
+            }
+        }
+        nc::timeout(-1);
+        typed
     }
 
     pub fn teardown() {
+        /* Refreshing after endwin would bring the screen back */
+        if nc::isendwin() {
+            return;
+        }
         nc::clear();
         nc::refresh();
         nc::doupdate();
@@ -340,81 +1115,575 @@ pub mod curses {
 mod pp {
     /* Pretty printer */
     use crate::state::{SearchMode, State, View};
-    use crate::ui::UserInterface;
+    use crate::ui::{UserInterface, VimMode};
     use ncurses as nc;
+    use regex::Regex;
     use std::env;
-    use unicode_width::UnicodeWidthStr;
+    use unicode_width::UnicodeWidthChar;
 
     pub fn status_bar(state: &State, user_interface: &UserInterface) -> String {
+        if let Some(template) = &user_interface.status_format {
+            return custom_status_bar(template, state, user_interface);
+        }
         let total_pages = user_interface.total_pages(state);
         format!(
-            "- view:{} (C-/) - search:{} (C-e) - case:{} (C-t) - page {}/{} -",
+            "- view:{}{} (C-/) - search:{}{} (C-e) - case:{}{} (C-t){}{}{}{}{}{}{}{}{} - matches: {} - page {}/{} -",
             view(state.view),
+            view_filter(state),
             search_mode(state.search_mode),
-            case(state.case_sensitivity),
+            regex_error(state.invalid_regex),
+            case(state.is_case_sensitive()),
+            smart_case(state.smart_case),
+            privilege_filter(state.privileged_only),
+            length_filter(state.length_range()),
+            unique_filter(state.dedupe),
+            reversed(state),
+            wrap_mode(user_interface.wrap),
+            read_only(state.read_only),
+            no_favorites(state.favorites_enabled),
+            vim_mode(user_interface.vim_mode),
+            editing(user_interface.editing.is_some()),
+            matches(state),
             current_page(user_interface.page, total_pages),
             total_pages,
         )
     }
 
-    pub fn top_bar(query: &str) -> String {
-        format!("{} {}", get_shell_prompt(), query)
+    pub fn custom_status_bar(
+        template: &str,
+        state: &State,
+        user_interface: &UserInterface,
+    ) -> String {
+        /* Placeholders: {view}, {mode}, {case}, {count}, {page} and {pages} */
+        let total_pages = user_interface.total_pages(state);
+        template
+            .replace(
+                "{view}",
+                &(view(state.view).to_string() + &view_filter(state)),
+            )
+            .replace(
+                "{mode}",
+                &(search_mode(state.search_mode).to_string() + regex_error(state.invalid_regex)),
+            )
+            .replace("{case}", case(state.is_case_sensitive()))
+            .replace("{count}", &matches(state).to_string())
+            .replace(
+                "{page}",
+                &current_page(user_interface.page, total_pages).to_string(),
+            )
+            .replace("{pages}", &total_pages.to_string())
     }
 
-    pub fn get_shell_prompt() -> String {
-        format!(
-            "{}@{}$",
-            env::var("USER").unwrap(),
-            gethostname::gethostname().into_string().unwrap()
-        )
+    pub fn no_favorites(favorites_enabled: bool) -> &'static str {
+        if favorites_enabled {
+            ""
+        } else {
+            " - favorites off"
+        }
     }
 
-    pub fn view(value: View) -> &'static str {
-        match value {
-            View::Sorted => "sorted",
-            View::Favorites => "favorites",
-            View::All => "all",
+    pub fn editing(value: bool) -> &'static str {
+        if value {
+            " - editing"
+        } else {
+            ""
         }
     }
 
-    pub fn search_mode(value: SearchMode) -> &'static str {
+    pub fn vim_mode(value: Option<VimMode>) -> &'static str {
         match value {
-            SearchMode::Exact => "exact",
-            SearchMode::Regex => "regex",
-            SearchMode::Fuzzy => "fuzzy",
+            Some(VimMode::Normal) => " - NORMAL",
+            Some(VimMode::Insert) => " - INSERT",
+            None => "",
         }
     }
 
-    pub fn case(value: bool) -> &'static str {
-        if value {
-            "sensitive"
-        } else {
-            "insensitive"
-        }
+    pub fn matches(state: &State) -> usize {
+        /* The current view holds what is left after the search */
+        state.commands(state.view).len()
     }
 
-    fn current_page(current_page: i32, total_pages: i32) -> i32 {
-        match total_pages {
-            0 => 0,
-            _ => current_page,
-        }
+    pub fn wrap(command: &str, width: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        command.split('\n').for_each(|line| {
+            let mut current = String::new();
+            let mut current_width = 0;
+            printable(line).chars().for_each(|ch| {
+                let ch_width = ch.width().unwrap_or(0);
+                if current_width + ch_width > width && !current.is_empty() {
+                    lines.push(std::mem::take(&mut current));
+                    current_width = 0;
+                }
+                current.push(ch);
+                current_width += ch_width;
+            });
+            lines.push(current);
+        });
+        lines
     }
 
-    pub fn deletion_prompt(command: &str) -> String {
-        format!("Do you want to delete all occurences of {}? y/n", command)
+    pub fn clamp_scroll(scroll: usize, lines: usize, rows: usize) -> usize {
+        scroll.min(lines.saturating_sub(rows))
     }
 
-    pub fn ljust(string: &str) -> String {
-        let overhead = string.width() - string.chars().count();
-        format!("{0:1$}", string, nc::COLS() as usize - 2 - overhead)
+    pub fn top_bar(prompt: &str, query: &str) -> String {
+        format!("{} {}", prompt, query)
     }
-}
 
-pub fn get_char_widths(string: &str) -> Vec<usize> {
-    string
-        .chars()
-        .map(|ch| ch.width().unwrap_or(0))
-        .collect::<Vec<usize>>()
+    pub fn get_shell_prompt(template: Option<&str>) -> String {
+        let user = user_name(env::var("USER").ok(), env::var("LOGNAME").ok());
+        shell_prompt(template, &user, host_name().as_deref())
+    }
+
+    pub fn shell_prompt(template: Option<&str>, user: &str, host: Option<&str>) -> String {
+        /* Placeholders: {user} and {host}. Without a host,
+         * the default prompt leaves the @host part out
+         */
+        match (template, host) {
+            (Some(template), _) => template
+                .replace("{user}", user)
+                .replace("{host}", host.unwrap_or_default()),
+            (None, Some(host)) => format!("{}@{}$", user, host),
+            (None, None) => format!("{}$", user),
+        }
+    }
+
+    fn host_name() -> Option<String> {
+        /* A name that isn't UTF-8 is shown as well as it can be */
+        let mut buffer = [0u8; 256];
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as _, buffer.len()) };
+        if result != 0 {
+            return None;
+        }
+        let name = buffer.split(|&byte| byte == 0).next().unwrap_or_default();
+        Some(String::from_utf8_lossy(name).into_owned()).filter(|name| !name.is_empty())
+    }
+
+    pub fn user_name(user: Option<String>, logname: Option<String>) -> String {
+        /* USER isn't set everywhere, e.g. in some containers
+         * or after su, so the name is looked up like whoami does
+         */
+        user.filter(|name| !name.is_empty())
+            .or_else(|| logname.filter(|name| !name.is_empty()))
+            .or_else(login_name)
+            .unwrap_or_else(|| String::from("unknown"))
+    }
+
+    fn login_name() -> Option<String> {
+        let passwd = unsafe { libc::getpwuid(libc::geteuid()) };
+        if passwd.is_null() {
+            return None;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr((*passwd).pw_name) };
+        name.to_str()
+            .ok()
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+    }
+
+    pub fn view(value: View) -> &'static str {
+        match value {
+            View::Sorted => "sorted",
+            View::Favorites => "favorites",
+            View::All => "all",
+            View::ThisDir => "dir",
+            View::Combined => "combined",
+            View::Timeline => "timeline",
+        }
+    }
+
+    pub fn view_filter(state: &State) -> String {
+        match state.view {
+            View::Sorted => format!("[{}]", state.sort_mode.as_ref()),
+            View::Favorites => match &state.tag {
+                Some(tag) => format!("[{}]", tag),
+                None => String::new(),
+            },
+            View::ThisDir => match state.cwd.file_name() {
+                Some(name) => format!("[{}]", name.to_string_lossy()),
+                None => format!("[{}]", state.cwd.display()),
+            },
+            _ => String::new(),
+        }
+    }
+
+    pub fn search_mode(value: SearchMode) -> &'static str {
+        match value {
+            SearchMode::Exact => "exact",
+            SearchMode::Regex => "regex",
+            SearchMode::Fuzzy => "fuzzy",
+            SearchMode::Loose => "loose",
+        }
+    }
+
+    pub fn regex_error(value: bool) -> &'static str {
+        if value {
+            " [invalid regex]"
+        } else {
+            ""
+        }
+    }
+
+    pub fn case(value: bool) -> &'static str {
+        if value {
+            "sensitive"
+        } else {
+            "insensitive"
+        }
+    }
+
+    pub fn smart_case(value: bool) -> &'static str {
+        if value {
+            " (smart)"
+        } else {
+            ""
+        }
+    }
+
+    pub fn privilege_filter(value: bool) -> &'static str {
+        if value {
+            " - privileged only (C-x)"
+        } else {
+            ""
+        }
+    }
+
+    pub fn length_filter(range: Option<String>) -> String {
+        match range {
+            Some(range) => format!(" - length:{} (F4)", range),
+            None => String::new(),
+        }
+    }
+
+    pub fn unique_filter(value: bool) -> &'static str {
+        if value {
+            " - unique (C-b)"
+        } else {
+            ""
+        }
+    }
+
+    pub fn reversed(state: &State) -> &'static str {
+        /* Only the sorted view can be reversed */
+        if state.reversed && state.view == View::Sorted {
+            " - reversed (C-r)"
+        } else {
+            ""
+        }
+    }
+
+    pub fn wrap_mode(value: bool) -> &'static str {
+        if value {
+            " - wrap (C-l)"
+        } else {
+            ""
+        }
+    }
+
+    pub fn read_only(value: bool) -> &'static str {
+        if value {
+            " - read-only"
+        } else {
+            ""
+        }
+    }
+
+    fn current_page(current_page: i32, total_pages: i32) -> i32 {
+        match total_pages {
+            0 => 0,
+            _ => current_page,
+        }
+    }
+
+    pub fn single_line(command: &str) -> String {
+        printable(&command.replace('\n', " ↵ "))
+    }
+
+    pub fn redact(command: &str, patterns: &[Regex], mask: char) -> String {
+        /* Only the first group is masked if the pattern has one,
+         * e.g. `--password=(\S+)`, otherwise the whole match is
+         */
+        let masked = patterns
+            .iter()
+            .flat_map(|r| r.captures_iter(command))
+            .map(|c| c.get(1).or_else(|| c.get(0)).unwrap().range())
+            .collect::<Vec<_>>();
+        command
+            .char_indices()
+            .map(|(idx, ch)| {
+                if masked.iter().any(|range| range.contains(&idx)) {
+                    mask
+                } else {
+                    ch
+                }
+            })
+            .collect()
+    }
+
+    pub fn printable(command: &str) -> String {
+        /* Control characters would be interpreted by the terminal,
+         * so they are shown as placeholders instead
+         */
+        command
+            .chars()
+            .map(|ch| match ch {
+                '\t' => ' ',
+                ch if ch.is_control() => '\u{fffd}',
+                ch => ch,
+            })
+            .collect()
+    }
+
+    pub fn empty_message(query: &str) -> &'static str {
+        if query.is_empty() {
+            "No commands in history"
+        } else {
+            "No matching commands"
+        }
+    }
+
+    pub fn tags_prompt(command: &str, tags: &str) -> String {
+        format!("Tags for {} (ENTER save, ESC cancel): {}", command, tags)
+    }
+
+    pub fn note_prompt(command: &str, note: &str) -> String {
+        format!("Note for {} (ENTER save, ESC cancel): {}", command, note)
+    }
+
+    pub fn deletion_prompt(command: &str, all_occurrences: bool) -> String {
+        if all_occurrences {
+            format!("Do you want to delete all occurences of {}? y/n", command)
+        } else {
+            format!("Do you want to delete this occurence of {}? y/n", command)
+        }
+    }
+
+    pub fn length_range_prompt(range: &str) -> String {
+        format!(
+            "Length range, like 20-, -10 or 5-40 (ENTER apply, ESC cancel): {}",
+            range
+        )
+    }
+
+    pub fn batch_deletion_prompt(count: usize) -> String {
+        match count {
+            1 => String::from("Do you want to delete the matching command? y/n"),
+            _ => format!("Do you want to delete all {} matching commands? y/n", count),
+        }
+    }
+
+    pub fn ljust(string: &str) -> String {
+        super::fit(string, (nc::COLS() - 2).max(0) as usize)
+    }
+}
+
+fn fit(string: &str, width: usize) -> String {
+    /* Padded or cut to `width` columns; anything longer would
+     * wrap onto the next row. format! pads by chars, not by columns.
+     */
+    let mut used = 0;
+    let fitting = string
+        .chars()
+        .take_while(|ch| {
+            used += ch.width().unwrap_or(0);
+            used <= width
+        })
+        .collect::<String>();
+    let padding = width - fitting.width();
+    fitting + &" ".repeat(padding)
+}
+
+fn usable(size: i32) -> usize {
+    /* What is left of the terminal for commands, never less than
+     * one row or column, so a tiny split pane doesn't crash
+     */
+    size.max(1) as usize
+}
+
+fn text_width(favorite: bool) -> usize {
+    /* Columns left for the command once the marker is in */
+    let width = usable(nc::COLS() - 2);
+    if favorite {
+        width.saturating_sub(FAVORITE_MARKER.width()).max(1)
+    } else {
+        width
+    }
+}
+
+fn gutter(favorite: bool, line_idx: usize) -> String {
+    /* The marker goes on the first row of a favorite, and
+     * wrapped rows are indented to line up with the command
+     */
+    match (favorite, line_idx) {
+        (false, _) => String::new(),
+        (true, 0) => String::from(FAVORITE_MARKER),
+        (true, _) => " ".repeat(FAVORITE_MARKER.width()),
+    }
+}
+
+fn note_text(note: &str, room: usize) -> Option<String> {
+    /* Cut off to what fits after the command, and left out
+     * if not even the start of the note would
+     */
+    const MIN_ROOM: usize = 8;
+    if room < MIN_ROOM {
+        return None;
+    }
+    let mut used = 0;
+    Some(
+        format!("  # {}", note)
+            .chars()
+            .take_while(|ch| {
+                used += ch.width().unwrap_or(0);
+                used <= room
+            })
+            .collect(),
+    )
+}
+
+fn time_text(format: TimeFormat, timestamp: u64, now: u64) -> String {
+    match format {
+        TimeFormat::Relative => relative_time(now.saturating_sub(timestamp)),
+        TimeFormat::Absolute => absolute_time(timestamp),
+    }
+}
+
+fn relative_time(seconds: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    const MONTH: u64 = 30 * DAY;
+    const YEAR: u64 = 365 * DAY;
+    match seconds {
+        s if s < MINUTE => String::from("now"),
+        s if s < HOUR => format!("{}m ago", s / MINUTE),
+        s if s < DAY => format!("{}h ago", s / HOUR),
+        s if s < MONTH => format!("{}d ago", s / DAY),
+        s if s < YEAR => format!("{}mo ago", s / MONTH),
+        s => format!("{}y ago", s / YEAR),
+    }
+}
+
+fn absolute_time(timestamp: u64) -> String {
+    /* In local time, the way `date` shows it */
+    let time = timestamp as libc::time_t;
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::new();
+    }
+    let mut buffer = [0u8; 32];
+    let written = unsafe {
+        libc::strftime(
+            buffer.as_mut_ptr() as _,
+            buffer.len(),
+            "%Y-%m-%d %H:%M\0".as_ptr() as _,
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buffer[..written]).into_owned()
+}
+
+fn style(pair: i16) -> nc::attr_t {
+    /* Without colors, the color pairs from init_color_pairs
+     * are told apart by attributes instead
+     */
+    if nc::has_colors() {
+        return nc::COLOR_PAIR(pair);
+    }
+    match pair {
+        2 | 3 => nc::A_REVERSE(),
+        4 | 7 => nc::A_BOLD(),
+        5 => nc::A_UNDERLINE(),
+        6 => nc::A_REVERSE() | nc::A_BOLD(),
+        _ => nc::A_NORMAL(),
+    }
+}
+
+pub fn is_vim_key(ch: u32) -> bool {
+    /* Control keys keep working in normal mode */
+    matches!(std::char::from_u32(ch), Some(ch) if !ch.is_control())
+}
+
+fn edit_line(line: &mut String, key: i32) -> Option<bool> {
+    /* Some(true) once ENTER is pressed, Some(false) on ESC, or
+     * when input fails, which would otherwise be read over and over
+     */
+    const ENTER: i32 = b'\n' as i32;
+    const ESC: i32 = 27;
+    const DEL: i32 = 127;
+    const BACKSPACE: i32 = 8;
+    match key {
+        ENTER | nc::KEY_ENTER => Some(true),
+        ESC | nc::ERR => Some(false),
+        DEL | BACKSPACE | nc::KEY_BACKSPACE => {
+            line.pop();
+            None
+        }
+        0x20..=0x7e => {
+            line.push(key as u8 as char);
+            None
+        }
+        _ => None,
+    }
+}
+
+fn is_confirmation(key: i32) -> Option<bool> {
+    const LOWERCASE_Y: i32 = b'y' as i32;
+    const UPPERCASE_Y: i32 = b'Y' as i32;
+    const LOWERCASE_N: i32 = b'n' as i32;
+    const UPPERCASE_N: i32 = b'N' as i32;
+    const ENTER: i32 = b'\n' as i32;
+    const ESC: i32 = 27;
+    match key {
+        LOWERCASE_Y | UPPERCASE_Y | ENTER | nc::KEY_ENTER => Some(true),
+        LOWERCASE_N | UPPERCASE_N | ESC | nc::ERR => Some(false),
+        _ => None,
+    }
+}
+
+fn answer(mut keys: impl Iterator<Item = i32>) -> bool {
+    /* Keys other than yes or no are ignored, so a stray key
+     * press doesn't answer the prompt. Failing input is a no.
+     */
+    keys.find_map(is_confirmation).unwrap_or(false)
+}
+
+pub fn get_char_widths(string: &str) -> Vec<usize> {
+    string
+        .chars()
+        .map(|ch| ch.width().unwrap_or(0))
+        .collect::<Vec<usize>>()
+}
+
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "kebab_case")]
+pub enum Navigation {
+    Paged,
+    Stay,
+    WrapInPage,
+}
+
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "kebab_case")]
+pub enum TimeFormat {
+    Relative,
+    Absolute,
+}
+
+impl TimeFormat {
+    fn width(self) -> usize {
+        /* The longest text of each, like "11mo ago" */
+        match self {
+            TimeFormat::Relative => 8,
+            TimeFormat::Absolute => 16,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VimMode {
+    Normal,
+    Insert,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -495,47 +1764,1001 @@ mod tests {
     }
 
     #[rstest(
-        string,
-        substring,
+        navigation,
+        page,
+        selected,
+        direction,
+        expected_page,
+        expected_selected,
+        case(Navigation::Paged, 1, 5, Direction::Forward, 1, 6),
+        case(Navigation::Paged, 1, 6, Direction::Forward, 2, 0),
+        case(Navigation::Paged, 2, 0, Direction::Backward, 1, 6),
+        case(Navigation::Paged, 1, 0, Direction::Backward, 4, 4),
+        case(Navigation::Paged, 4, 4, Direction::Forward, 1, 0),
+        case(Navigation::Stay, 1, 5, Direction::Forward, 1, 6),
+        case(Navigation::Stay, 1, 6, Direction::Forward, 1, 6),
+        case(Navigation::Stay, 2, 0, Direction::Backward, 2, 0),
+        case(Navigation::Stay, 4, 4, Direction::Forward, 4, 4),
+        case(Navigation::WrapInPage, 1, 5, Direction::Forward, 1, 6),
+        case(Navigation::WrapInPage, 1, 6, Direction::Forward, 1, 0),
+        case(Navigation::WrapInPage, 2, 0, Direction::Backward, 2, 6),
+        case(Navigation::WrapInPage, 4, 4, Direction::Forward, 4, 0)
+    )]
+    fn move_selected(
+        navigation: Navigation,
+        page: i32,
+        selected: i32,
+        direction: Direction,
+        expected_page: i32,
+        expected_selected: i32,
+        fake_state: State,
+    ) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.navigation = navigation;
+        user_interface.page = page;
+        user_interface.selected = selected;
+        user_interface.move_selected(&fake_state, direction);
+        assert_eq!(user_interface.page, expected_page);
+        assert_eq!(user_interface.selected, expected_selected);
+    }
+
+    #[rstest()]
+    fn move_selected_onto_last_page(fake_state: State) {
+        /* The 4th page only holds 5 commands */
+        let mut user_interface = UserInterface::new("");
+        user_interface.page = 3;
+        user_interface.selected = 6;
+        user_interface.turn_page(&fake_state, Direction::Forward);
+        assert_eq!((user_interface.page, user_interface.selected), (4, 4));
+        user_interface.move_selected(&fake_state, Direction::Backward);
+        assert_eq!((user_interface.page, user_interface.selected), (4, 3));
+        user_interface.move_selected(&fake_state, Direction::Forward);
+        user_interface.move_selected(&fake_state, Direction::Forward);
+        assert_eq!((user_interface.page, user_interface.selected), (1, 0));
+    }
+
+    #[rstest(
+        key,
+        expected,
+        case(b'y' as i32, Some(true)),
+        case(b'Y' as i32, Some(true)),
+        case(b'\n' as i32, Some(true)),
+        case(nc::KEY_ENTER, Some(true)),
+        case(b'n' as i32, Some(false)),
+        case(b'N' as i32, Some(false)),
+        case(27, Some(false)),
+        case(nc::ERR, Some(false)),
+        case(nc::KEY_DC, None),
+        case(0, None)
+    )]
+    fn is_confirmation(key: i32, expected: Option<bool>) {
+        assert_eq!(super::is_confirmation(key), expected);
+    }
+
+    #[rstest(
+        keys,
+        expected_line,
+        expected,
+        case(vec![b'd' as i32, b'b' as i32, 10], "db", Some(true)),
+        case(vec![b'x' as i32, 127, b'y' as i32, 27], "y", Some(false)),
+        case(vec![nc::KEY_BACKSPACE, nc::KEY_BACKSPACE], "", None),
+        case(vec![nc::KEY_DC, 200, b' ' as i32], " ", None),
+        case(vec![b'l' as i32, nc::ERR], "l", Some(false))
+    )]
+    fn edit_line(keys: Vec<i32>, expected_line: &str, expected: Option<bool>) {
+        let mut line = String::new();
+        let done = keys
+            .into_iter()
+            .find_map(|key| super::edit_line(&mut line, key));
+        assert_eq!(line, expected_line);
+        assert_eq!(done, expected);
+    }
+
+    #[rstest(
+        keys,
         expected,
-        case("cat spam", "cat", vec![0, 1, 2]),
-        case("make -j4", "[0-9]+", vec![7]),
-        case("ping -c 10 www.google.com", "[0-9]+", vec![8, 9])
+        case(vec![b'y' as i32], true),
+        case(vec![27], false),
+        case(vec![0, nc::KEY_DC, b'x' as i32, b'y' as i32], true),
+        case(vec![nc::KEY_RESIZE, b'n' as i32, b'y' as i32], false),
+        case(vec![0, nc::ERR, b'y' as i32], false),
+        case(vec![0, 0], false)
     )]
-    fn matched_chars_indices(string: &str, substring: &str, expected: Vec<usize>) {
+    fn answer(keys: Vec<i32>, expected: bool) {
+        assert_eq!(super::answer(keys.into_iter()), expected);
+    }
+
+    #[rstest(
+        keys,
+        expected,
+        case(vec![b'x' as i32, b'Y' as i32], true),
+        case(vec![nc::KEY_ENTER], true),
+        case(vec![b'n' as i32, b'y' as i32], false),
+        case(vec![], false)
+    )]
+    fn confirm(keys: Vec<i32>, expected: bool) {
+        /* Keys put back are read last in, first out */
+        keys.iter().rev().for_each(|&key| {
+            nc::ungetch(key);
+        });
         let user_interface = UserInterface::new("");
+        assert_eq!(user_interface.confirm(), expected);
+    }
+
+    #[rstest(
+        page,
+        selected,
+        case(1, 7),
+        case(1, 100),
+        case(1, -1),
+        case(5, 0),
+        case(100, 3)
+    )]
+    fn selected_out_of_range(page: i32, selected: i32, fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.page = page;
+        user_interface.selected = selected;
+        assert_eq!(user_interface.selected(&fake_state), None);
+        assert_eq!(user_interface.selected_position(&fake_state), None);
+    }
+
+    #[rstest(wrap, selected, case(false, 0), case(false, 3), case(true, 3))]
+    fn selected_position(wrap: bool, selected: i32, fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.wrap = wrap;
+        user_interface.selected = selected;
         assert_eq!(
-            user_interface.substring_indices(string, substring),
-            expected
+            user_interface.selected_position(&fake_state),
+            Some(selected as usize)
         );
     }
 
     #[rstest()]
-    fn page_size(fake_state: State) {
-        let user_interface = UserInterface::new("");
-        assert_eq!(user_interface.page_size(&fake_state), 7);
+    fn retain_selected_on_single_command_page(mut fake_state: State) {
+        fake_state.commands.sorted.truncate(22);
+        let mut user_interface = UserInterface::new("");
+        user_interface.page = 4;
+        user_interface.retain_selected(&fake_state);
+        assert_eq!(user_interface.selected, 0);
+        fake_state.commands.sorted.pop();
+        user_interface.clamp_selection(&fake_state);
+        assert_eq!(user_interface.page, 3);
+        assert_eq!(user_interface.selected, 0);
+        assert_eq!(
+            user_interface.selected(&fake_state),
+            Some(String::from("deactivate"))
+        );
     }
 
     #[rstest()]
-    fn total_pages(fake_state: State) {
-        let user_interface = UserInterface::new("");
-        assert_eq!(user_interface.total_pages(&fake_state), 4);
+    fn select_first(fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.page = 3;
+        user_interface.selected = 4;
+        user_interface.select_first();
+        assert_eq!(user_interface.page, 1);
+        assert_eq!(user_interface.selected, 0);
+        assert_eq!(
+            user_interface.selected(&fake_state),
+            Some(String::from("cat spam"))
+        );
+    }
+
+    #[rstest()]
+    fn toggle_view(mut fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.page = 3;
+        user_interface.selected = 2;
+        user_interface.toggle_view(&mut fake_state);
+        assert_eq!(fake_state.view, View::Favorites);
+        assert_eq!((user_interface.page, user_interface.selected), (1, 0));
+        while fake_state.view != View::Sorted {
+            user_interface.toggle_view(&mut fake_state);
+        }
+        assert_eq!((user_interface.page, user_interface.selected), (3, 2));
+        user_interface.toggle_view(&mut fake_state);
+        user_interface.select_first();
+        while fake_state.view != View::Sorted {
+            user_interface.toggle_view(&mut fake_state);
+        }
+        assert_eq!((user_interface.page, user_interface.selected), (1, 0));
+    }
+
+    #[rstest(show_help, expected_selected, case(true, 4), case(false, 1))]
+    fn select_last(show_help: bool, expected_selected: i32, fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.show_help = show_help;
+        user_interface.select_last(&fake_state);
+        assert_eq!(user_interface.page, 4);
+        assert_eq!(user_interface.selected, expected_selected);
+        assert_eq!(
+            user_interface.selected(&fake_state),
+            Some(String::from("cd /home/bwk/"))
+        );
+    }
+
+    #[rstest()]
+    fn select_last_without_commands(mut fake_state: State) {
+        fake_state.view = View::Favorites;
+        let mut user_interface = UserInterface::new("");
+        user_interface.select_last(&fake_state);
+        assert_eq!(user_interface.page, 1);
+        assert_eq!(user_interface.selected, 0);
+    }
+
+    #[rstest(
+        key,
+        handled,
+        expected_selected,
+        expected_page,
+        expected_mode,
+        case('j', true, 1, 1, VimMode::Normal),
+        case('k', true, 4, 4, VimMode::Normal),
+        case('l', true, 0, 2, VimMode::Normal),
+        case('h', true, 0, 4, VimMode::Normal),
+        case('/', true, 0, 1, VimMode::Insert),
+        case('x', true, 0, 1, VimMode::Normal),
+        case('\n', false, 0, 1, VimMode::Normal)
+    )]
+    fn vim_key(
+        key: char,
+        handled: bool,
+        expected_selected: i32,
+        expected_page: i32,
+        expected_mode: VimMode,
+        fake_state: State,
+    ) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.vim_mode = Some(VimMode::Normal);
+        assert_eq!(super::is_vim_key(key as u32), handled);
+        user_interface.vim_key(&fake_state, key as u32);
+        assert_eq!(user_interface.selected, expected_selected);
+        assert_eq!(user_interface.page, expected_page);
+        assert_eq!(user_interface.vim_mode, Some(expected_mode));
     }
 
     #[rstest(
         value,
         expected,
-        case(View::Sorted, "sorted"),
-        case(View::Favorites, "favorites"),
-        case(View::All, "all")
+        case(Some(VimMode::Normal), " - NORMAL"),
+        case(Some(VimMode::Insert), " - INSERT"),
+        case(None, "")
     )]
-    fn format_view(value: View, expected: &str) {
-        assert_eq!(super::pp::view(value), expected);
+    fn format_vim_mode(value: Option<VimMode>, expected: &str) {
+        assert_eq!(super::pp::vim_mode(value), expected);
     }
 
-    #[rstest(value, expected, case(true, "sensitive"), case(false, "insensitive"))]
-    fn format_case(value: bool, expected: &str) {
-        assert_eq!(super::pp::case(value), expected);
+    #[rstest(
+        moves,
+        text,
+        expected,
+        case(0, "x", "xšampion"),
+        case(1, "x", "šxampion"),
+        case(7, "x", "šampionx"),
+        case(1, "xy", "šxyampion")
+    )]
+    fn insert_at_cursor(moves: usize, text: &str, expected: &str, mut fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        fake_state.query = String::from("šampion");
+        user_interface.cursor.chars_moved = moves;
+        user_interface.insert_in_query(&mut fake_state, text);
+        assert_eq!(fake_state.query, expected);
+    }
+
+    #[rstest(
+        column,
+        expected,
+        case(0, "šampion"),
+        case(1, "ampion"),
+        case(3, "šapion"),
+        case(7, "šampio")
+    )]
+    fn remove_at_cursor(column: usize, expected: &str, mut fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        fake_state.query = String::from("šampion");
+        user_interface.cursor.column = column;
+        assert_eq!(
+            user_interface.remove_char_from_query(&fake_state.query),
+            expected
+        );
+    }
+
+    #[rstest(
+        page,
+        row,
+        expected,
+        case(1, 0, 0),
+        case(1, 6, 6),
+        case(1, 7, 3),
+        case(4, 4, 4),
+        case(4, 5, 3),
+        case(5, 0, 3)
+    )]
+    fn select_row(page: i32, row: i32, expected: i32, fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.page = page;
+        user_interface.selected = 3;
+        user_interface.select_row(&fake_state, row);
+        assert_eq!(user_interface.selected, expected);
+    }
+
+    #[rstest(
+        selected,
+        scrolls,
+        direction,
+        expected,
+        case(0, 1, Direction::Forward, 0),
+        case(5, 1, Direction::Forward, 0),
+        case(0, 3, Direction::Backward, 0)
+    )]
+    fn scroll_selected(
+        selected: i32,
+        scrolls: usize,
+        direction: Direction,
+        expected: usize,
+        fake_state: State,
+    ) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.selected = selected;
+        (0..scrolls).for_each(|_| user_interface.scroll_selected(&fake_state, direction));
+        assert_eq!(user_interface.offset, expected);
+    }
+
+    #[rstest(favorite, expected, case(false, 1), case(true, 2))]
+    fn row_height(favorite: bool, expected: usize) {
+        /* The marker takes 2 of the 78 columns */
+        let user_interface = UserInterface::new("");
+        assert_eq!(
+            user_interface.row_height(&"a".repeat(78), favorite),
+            expected
+        );
+    }
+
+    #[rstest(
+        favorite,
+        line_idx,
+        expected,
+        case(false, 0, ""),
+        case(true, 0, "\u{2605} "),
+        case(true, 1, "  ")
+    )]
+    fn gutter(favorite: bool, line_idx: usize, expected: &str) {
+        assert_eq!(super::gutter(favorite, line_idx), expected);
+    }
+
+    #[rstest(
+        page,
+        expected_size,
+        case(1, 1),
+        case(2, 1),
+        case(3, 7),
+        case(6, 5),
+        case(7, 0)
+    )]
+    fn wrapped_pages(page: i32, expected_size: i32, mut fake_state: State) {
+        /* 78 columns and 7 rows for commands: the first command takes
+         * 3 rows, the second one is cut to a page, the rest take one
+         */
+        fake_state.commands.sorted.insert(0, "a".repeat(1000));
+        fake_state.commands.sorted.insert(0, "a".repeat(200));
+        let mut user_interface = UserInterface::new("");
+        user_interface.wrap = true;
+        user_interface.page = page;
+        assert_eq!(user_interface.total_pages(&fake_state), 6);
+        assert_eq!(user_interface.page_size(&fake_state), expected_size);
+        let pages = user_interface.wrapped_pages(&fake_state, page as usize);
+        assert_eq!(pages.len(), (page as usize).min(6));
+    }
+
+    #[rstest()]
+    fn move_selected_wrapped(mut fake_state: State) {
+        /* 3 + 1 + 3 rows fill up the first page */
+        fake_state.commands.sorted.insert(0, "a".repeat(200));
+        fake_state.commands.sorted.insert(2, "a".repeat(200));
+        let mut user_interface = UserInterface::new("");
+        user_interface.toggle_wrap();
+        let mut selected = Vec::new();
+        (0..8).for_each(|_| {
+            user_interface.move_selected(&fake_state, Direction::Forward);
+            selected.push((user_interface.page, user_interface.selected));
+        });
+        assert_eq!(
+            selected,
+            [
+                (1, 1),
+                (1, 2),
+                (2, 0),
+                (2, 1),
+                (2, 2),
+                (2, 3),
+                (2, 4),
+                (2, 5)
+            ]
+        );
+        user_interface.scroll_selected(&fake_state, Direction::Forward);
+        assert_eq!(user_interface.offset, 0);
+    }
+
+    #[rstest(scrolls, expected, case(1, 1), case(10, 10), case(500, 22))]
+    fn scroll_long_command(scrolls: usize, expected: usize, mut fake_state: State) {
+        let command = format!("echo {}", "a".repeat(95));
+        fake_state.commands.sorted.insert(0, command);
+        let mut user_interface = UserInterface::new("");
+        (0..scrolls).for_each(|_| user_interface.scroll_selected(&fake_state, Direction::Forward));
+        assert_eq!(user_interface.offset, expected);
+        user_interface.scroll_selected(&fake_state, Direction::Backward);
+        assert_eq!(user_interface.offset, expected - 1);
+        user_interface.move_selected(&fake_state, Direction::Forward);
+        assert_eq!(user_interface.offset, 0);
+    }
+
+    #[rstest(
+        value,
+        expected,
+        case("paged", Navigation::Paged),
+        case("stay", Navigation::Stay),
+        case("wrap-in-page", Navigation::WrapInPage)
+    )]
+    fn parse_navigation(value: &str, expected: Navigation) {
+        assert_eq!(value.parse::<Navigation>().unwrap(), expected);
+    }
+
+    #[rstest(
+        string,
+        substring,
+        search_mode,
+        case_sensitive,
+        expected,
+        case("cat spam", "cat", SearchMode::Exact, false, vec![0, 1, 2]),
+        case("make -j4", "[0-9]+", SearchMode::Regex, false, vec![7]),
+        case("ping -c 10 www.google.com", "[0-9]+", SearchMode::Regex, false, vec![8, 9]),
+        case("make -j4", "e-", SearchMode::Loose, false, vec![3, 4, 5]),
+        case("tar a.b axb", "a.b", SearchMode::Exact, false, vec![4, 5, 6]),
+        case("tar a.b axb", "a.b", SearchMode::Regex, false, vec![4, 5, 6, 8, 9, 10]),
+        case("make -j4", "[0-9]+", SearchMode::Exact, false, vec![]),
+        case("cat SPAM", "spam", SearchMode::Exact, false, vec![4, 5, 6, 7]),
+        case("cat SPAM", "spam", SearchMode::Exact, true, vec![])
+    )]
+    fn matched_chars_indices(
+        string: &str,
+        substring: &str,
+        search_mode: SearchMode,
+        case_sensitive: bool,
+        expected: Vec<usize>,
+    ) {
+        let user_interface = UserInterface::new("");
+        assert_eq!(
+            user_interface.substring_indices(string, substring, search_mode, case_sensitive),
+            expected
+        );
+    }
+
+    #[rstest(
+        enter_executes,
+        sticky,
+        expected,
+        case(true, false, "ENTER           run the command"),
+        case(false, false, "ENTER           paste the command, like TAB"),
+        case(true, true, "ENTER           copy the command, like TAB")
+    )]
+    fn help_lines(enter_executes: bool, sticky: bool, expected: &str) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.enter_executes = enter_executes;
+        user_interface.sticky = sticky;
+        let lines = user_interface.help_lines();
+        assert_eq!(lines[0], expected);
+        assert_eq!(lines.len(), super::HELP.len() + 2);
+        assert!(lines.contains(&String::from("F1              show this help")));
+    }
+
+    #[rstest(
+        note,
+        room,
+        expected,
+        case("database shell", 40, Some("  # database shell")),
+        case("database shell", 10, Some("  # databa")),
+        case("日本語", 9, Some("  # 日本")),
+        case("database shell", 7, None)
+    )]
+    fn note_text(note: &str, room: usize, expected: Option<&str>) {
+        assert_eq!(super::note_text(note, room).as_deref(), expected);
+    }
+
+    #[rstest(
+        string,
+        width,
+        expected,
+        case("ls", 4, "ls  "),
+        case("make -j4", 4, "make"),
+        case("日本語", 5, "日本 "),
+        case("ls", 0, "")
+    )]
+    fn fit(string: &str, width: usize, expected: &str) {
+        assert_eq!(super::fit(string, width), expected);
+    }
+
+    #[rstest(
+        seconds,
+        expected,
+        case(42, "now"),
+        case(180, "3m ago"),
+        case(3 * 3600 + 59, "3h ago"),
+        case(2 * 86400, "2d ago"),
+        case(334 * 86400, "11mo ago"),
+        case(3 * 365 * 86400, "3y ago")
+    )]
+    fn relative_time(seconds: u64, expected: &str) {
+        assert_eq!(super::relative_time(seconds), expected);
+    }
+
+    #[rstest()]
+    fn absolute_time() {
+        let text = super::absolute_time(1628000000);
+        let format = Regex::new(r"^\d{4}-\d{2}-\d{2} \d{2}:\d{2}$").unwrap();
+        assert!(format.is_match(&text));
+        assert_eq!(text.len(), TimeFormat::Absolute.width());
+    }
+
+    #[rstest(
+        timestamps,
+        expected_column,
+        expected_width,
+        case(None, 0, 78),
+        case(Some(TimeFormat::Relative), 10, 68),
+        case(Some(TimeFormat::Absolute), 18, 60)
+    )]
+    fn time_column(timestamps: Option<TimeFormat>, expected_column: usize, expected_width: usize) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.timestamps = timestamps;
+        assert_eq!(user_interface.time_column(), expected_column);
+        assert_eq!(user_interface.command_width(false), expected_width);
+    }
+
+    #[rstest()]
+    fn toggle_timestamps() {
+        let mut user_interface = UserInterface::new("");
+        let mut seen = Vec::new();
+        (0..3).for_each(|_| {
+            user_interface.toggle_timestamps();
+            seen.push(user_interface.timestamps);
+        });
+        assert_eq!(
+            seen,
+            [Some(TimeFormat::Relative), Some(TimeFormat::Absolute), None]
+        );
+    }
+
+    #[rstest(size, expected, case(78, 78), case(1, 1), case(0, 1), case(-2, 1))]
+    fn usable(size: i32, expected: usize) {
+        assert_eq!(super::usable(size), expected);
+    }
+
+    #[rstest(
+        enter_executes,
+        sticky,
+        expected,
+        case(true, false, "ENTER run, TAB paste"),
+        case(false, false, "ENTER/TAB paste"),
+        case(true, true, "ENTER/TAB copy")
+    )]
+    fn label(enter_executes: bool, sticky: bool, expected: &str) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.enter_executes = enter_executes;
+        user_interface.sticky = sticky;
+        assert!(user_interface.label().contains(expected));
+    }
+
+    #[rstest(
+        user,
+        logname,
+        expected,
+        case(Some("bwk"), Some("root"), "bwk"),
+        case(None, Some("root"), "root"),
+        case(Some(""), Some("root"), "root")
+    )]
+    fn user_name(user: Option<&str>, logname: Option<&str>, expected: &str) {
+        assert_eq!(
+            super::pp::user_name(user.map(String::from), logname.map(String::from)),
+            expected
+        );
+    }
+
+    #[rstest(
+        template,
+        host,
+        expected,
+        case(None, Some("vm"), "bwk@vm$"),
+        case(None, None, "bwk$"),
+        case(Some("{user}:{host}>"), Some("vm"), "bwk:vm>"),
+        case(Some("{user}@{host}"), None, "bwk@"),
+        case(Some(""), Some("vm"), "")
+    )]
+    fn shell_prompt(template: Option<&str>, host: Option<&str>, expected: &str) {
+        assert_eq!(super::pp::shell_prompt(template, "bwk", host), expected);
+    }
+
+    #[rstest()]
+    fn user_name_without_variables() {
+        assert!(!super::pp::user_name(None, None).is_empty());
+    }
+
+    #[rstest(show_help, expected, case(true, 3), case(false, 2))]
+    fn reserved_rows(show_help: bool, expected: i32) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.show_help = show_help;
+        assert_eq!(user_interface.reserved_rows(), expected);
+    }
+
+    #[rstest(show_help, expected, case(true, 7), case(false, 8))]
+    fn page_size(show_help: bool, expected: i32, fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.show_help = show_help;
+        assert_eq!(user_interface.page_size(&fake_state), expected);
+    }
+
+    #[rstest(
+        margin,
+        expected_size,
+        expected_pages,
+        case(0, 7, 4),
+        case(2, 5, 6),
+        case(6, 1, 26),
+        case(20, 1, 26)
+    )]
+    fn page_size_with_margin(
+        margin: i32,
+        expected_size: i32,
+        expected_pages: i32,
+        fake_state: State,
+    ) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.margin = margin;
+        assert_eq!(user_interface.page_size(&fake_state), expected_size);
+        assert_eq!(user_interface.total_pages(&fake_state), expected_pages);
+    }
+
+    #[rstest(
+        max_rows,
+        expected_size,
+        expected_pages,
+        case(Some(5), 5, 6),
+        case(Some(20), 7, 4),
+        case(Some(0), 1, 26),
+        case(None, 7, 4)
+    )]
+    fn page_size_with_max_rows(
+        max_rows: Option<usize>,
+        expected_size: i32,
+        expected_pages: i32,
+        fake_state: State,
+    ) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.max_rows = max_rows;
+        assert_eq!(user_interface.page_size(&fake_state), expected_size);
+        assert_eq!(user_interface.total_pages(&fake_state), expected_pages);
+    }
+
+    #[rstest(show_help, expected, case(true, 4), case(false, 4))]
+    fn total_pages(show_help: bool, expected: i32, fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.show_help = show_help;
+        assert_eq!(user_interface.total_pages(&fake_state), expected);
+    }
+
+    #[rstest(
+        page,
+        expected,
+        case(3, vec![
+            "cargo test",
+            "xfce4-panel -r",
+            "nano .gitignore",
+            "sudo dkms add .",
+            "cd ~/Downloads",
+            "make -j4",
+            "gpg --card-status",
+            "echo šampion",
+        ]),
+        case(4, vec!["nano .github/workflows/build.yml", "cd /home/bwk/"])
+    )]
+    fn get_page_without_help(page: i32, expected: Vec<&str>, fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.show_help = false;
+        user_interface.page = page;
+        assert_eq!(user_interface.page_contents(&fake_state), expected);
+    }
+
+    #[rstest(
+        value,
+        expected,
+        case(View::Sorted, "sorted"),
+        case(View::Favorites, "favorites"),
+        case(View::All, "all"),
+        case(View::ThisDir, "dir"),
+        case(View::Combined, "combined"),
+        case(View::Timeline, "timeline")
+    )]
+    fn format_view(value: View, expected: &str) {
+        assert_eq!(super::pp::view(value), expected);
+    }
+
+    #[rstest(
+        view,
+        cwd,
+        expected,
+        case(View::Sorted, "/home/bwk/hstr-rs", "[frequency]"),
+        case(View::All, "/home/bwk/hstr-rs", ""),
+        case(View::ThisDir, "/home/bwk/hstr-rs", "[hstr-rs]"),
+        case(View::ThisDir, "/", "[/]")
+    )]
+    fn format_view_filter(view: View, cwd: &str, expected: &str, mut fake_state: State) {
+        fake_state.view = view;
+        fake_state.cwd = std::path::PathBuf::from(cwd);
+        assert_eq!(super::pp::view_filter(&fake_state), expected);
+    }
+
+    #[rstest(
+        view,
+        tag,
+        expected,
+        case(View::Favorites, Some("deploy"), "[deploy]"),
+        case(View::Favorites, None, "")
+    )]
+    fn format_tag_filter(view: View, tag: Option<&str>, expected: &str, mut fake_state: State) {
+        fake_state.view = view;
+        fake_state.tag = tag.map(String::from);
+        assert_eq!(super::pp::view_filter(&fake_state), expected);
+    }
+
+    #[rstest(value, expected, case(true, "sensitive"), case(false, "insensitive"))]
+    fn format_case(value: bool, expected: &str) {
+        assert_eq!(super::pp::case(value), expected);
+    }
+
+    #[rstest(
+        all_occurrences,
+        expected,
+        case(true, "Do you want to delete all occurences of ls -la? y/n"),
+        case(false, "Do you want to delete this occurence of ls -la? y/n")
+    )]
+    fn format_deletion_prompt(all_occurrences: bool, expected: &str) {
+        assert_eq!(
+            super::pp::deletion_prompt("ls -la", all_occurrences),
+            expected
+        );
+    }
+
+    #[rstest(
+        command,
+        expected,
+        case("ls -la", "ls -la"),
+        case("for i in 1 2\ndo echo $i\ndone", "for i in 1 2 ↵ do echo $i ↵ done"),
+        case("echo \u{1b}[31mred", "echo \u{fffd}[31mred"),
+        case("printf 'a\tb\r'", "printf 'a b\u{fffd}'")
+    )]
+    fn format_single_line(command: &str, expected: &str) {
+        assert_eq!(super::pp::single_line(command), expected);
+    }
+
+    #[rstest(
+        query,
+        expected,
+        case("", "No commands in history"),
+        case("cargo", "No matching commands")
+    )]
+    fn format_empty_message(query: &str, expected: &str) {
+        assert_eq!(super::pp::empty_message(query), expected);
+    }
+
+    #[rstest(
+        view,
+        query,
+        expected,
+        case(View::Sorted, "", 26),
+        case(View::Sorted, "spam", 3),
+        case(View::Sorted, "cargo", 1),
+        case(View::Sorted, "doesnotexist", 0),
+        case(View::Favorites, "", 0)
+    )]
+    fn format_matches(view: View, query: &str, expected: usize, mut fake_state: State) {
+        fake_state.view = view;
+        fake_state.query = query.to_string();
+        fake_state.search();
+        assert_eq!(super::pp::matches(&fake_state), expected);
+    }
+
+    #[rstest(
+        command,
+        mask,
+        expected,
+        case("mysql --password=hunter2 db", '*', "mysql --password=******* db"),
+        case(
+            "export AWS_SECRET_ACCESS_KEY=abc",
+            '#',
+            "export #####################=abc"
+        ),
+        case("curl -u bwk:pass ix.io", '*', "curl -u bwk:pass ix.io"),
+        case("ls -la", '*', "ls -la")
+    )]
+    fn format_redact(command: &str, mask: char, expected: &str) {
+        let patterns = vec![
+            Regex::new(r"--password=(\S+)").unwrap(),
+            Regex::new(r"AWS_SECRET\w*").unwrap(),
+        ];
+        assert_eq!(super::pp::redact(command, &patterns, mask), expected);
+    }
+
+    #[rstest(
+        command,
+        width,
+        expected,
+        case("ls -la", 10, vec!["ls -la"]),
+        case("git push origin master", 10, vec!["git push o", "rigin mast", "er"]),
+        case("for i in 1 2\ndo echo $i\ndone", 8, vec!["for i in", " 1 2", "do echo ", "$i", "done"]),
+        case("echo šampion", 6, vec!["echo š", "ampion"]),
+        case("echo 日本語", 6, vec!["echo ", "日本語"]),
+        case("echo \u{7}", 10, vec!["echo \u{fffd}"]),
+        case("", 10, vec![""])
+    )]
+    fn format_wrap(command: &str, width: usize, expected: Vec<&str>) {
+        assert_eq!(super::pp::wrap(command, width), expected);
+    }
+
+    #[rstest(
+        scroll,
+        lines,
+        rows,
+        expected,
+        case(0, 3, 10, 0),
+        case(5, 3, 10, 0),
+        case(5, 20, 10, 5),
+        case(15, 20, 10, 10)
+    )]
+    fn clamp_scroll(scroll: usize, lines: usize, rows: usize, expected: usize) {
+        assert_eq!(super::pp::clamp_scroll(scroll, lines, rows), expected);
+    }
+
+    #[rstest(
+        page,
+        selected,
+        margin,
+        expected_page,
+        expected_selected,
+        case(1, 3, 0, 1, 3),
+        case(4, 6, 0, 4, 4),
+        case(9, 2, 0, 4, 2),
+        case(2, 6, 4, 2, 2),
+        case(9, 6, 4, 9, 1)
+    )]
+    fn clamp_selection(
+        page: i32,
+        selected: i32,
+        margin: i32,
+        expected_page: i32,
+        expected_selected: i32,
+        fake_state: State,
+    ) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.page = page;
+        user_interface.selected = selected;
+        user_interface.margin = margin;
+        user_interface.clamp_selection(&fake_state);
+        assert_eq!(user_interface.page, expected_page);
+        assert_eq!(user_interface.selected, expected_selected);
+        assert!(user_interface.selected(&fake_state).is_some());
+    }
+
+    #[rstest()]
+    fn clamp_selection_without_commands(mut fake_state: State) {
+        fake_state.commands.sorted.clear();
+        let mut user_interface = UserInterface::new("");
+        user_interface.page = 3;
+        user_interface.selected = 5;
+        user_interface.clamp_selection(&fake_state);
+        assert_eq!(user_interface.page, 1);
+        assert_eq!(user_interface.selected, 0);
+    }
+
+    #[rstest(
+        template,
+        expected,
+        case("{view} {mode} {case}", "sorted[frequency] exact insensitive"),
+        case("{count} matches, {page}/{pages}", "26 matches, 1/4"),
+        case("[{page}] {page}", "[1] 1"),
+        case("no placeholders {unknown}", "no placeholders {unknown}")
+    )]
+    fn custom_status_bar(template: &str, expected: &str, fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        user_interface.status_format = Some(template.to_string());
+        assert_eq!(
+            super::pp::status_bar(&fake_state, &user_interface),
+            expected
+        );
+    }
+
+    #[rstest(
+        count,
+        expected,
+        case(1, "Do you want to delete the matching command? y/n"),
+        case(42, "Do you want to delete all 42 matching commands? y/n")
+    )]
+    fn format_batch_deletion_prompt(count: usize, expected: &str) {
+        assert_eq!(super::pp::batch_deletion_prompt(count), expected);
+    }
+
+    #[rstest()]
+    fn edit_before_paste(mut fake_state: State) {
+        let mut user_interface = UserInterface::new("");
+        fake_state.query = String::from("git");
+        user_interface.page = 2;
+        user_interface.start_editing(&mut fake_state, "git push origin master");
+        assert_eq!(fake_state.query, "git push origin master");
+        assert_eq!(user_interface.page, 1);
+        fake_state.query = String::from("git push origin feature");
+        user_interface.stop_editing(&mut fake_state);
+        assert_eq!(fake_state.query, "git");
+        assert_eq!(user_interface.editing, None);
+        user_interface.stop_editing(&mut fake_state);
+        assert_eq!(fake_state.query, "git");
+    }
+
+    #[rstest(value, expected, case(true, " - editing"), case(false, ""))]
+    fn format_editing(value: bool, expected: &str) {
+        assert_eq!(super::pp::editing(value), expected);
+    }
+
+    #[rstest(value, expected, case(true, ""), case(false, " - favorites off"))]
+    fn format_no_favorites(value: bool, expected: &str) {
+        assert_eq!(super::pp::no_favorites(value), expected);
+    }
+
+    #[rstest(value, expected, case(true, " - read-only"), case(false, ""))]
+    fn format_read_only(value: bool, expected: &str) {
+        assert_eq!(super::pp::read_only(value), expected);
+    }
+
+    #[rstest(value, expected, case(true, " [invalid regex]"), case(false, ""))]
+    fn format_regex_error(value: bool, expected: &str) {
+        assert_eq!(super::pp::regex_error(value), expected);
+    }
+
+    #[rstest(
+        value,
+        expected,
+        case(true, " - privileged only (C-x)"),
+        case(false, "")
+    )]
+    fn format_privilege_filter(value: bool, expected: &str) {
+        assert_eq!(super::pp::privilege_filter(value), expected);
+    }
+
+    #[rstest(
+        range,
+        expected,
+        case(Some("20-"), " - length:20- (F4)"),
+        case(Some("5-40"), " - length:5-40 (F4)"),
+        case(None, "")
+    )]
+    fn format_length_filter(range: Option<&str>, expected: &str) {
+        assert_eq!(super::pp::length_filter(range.map(String::from)), expected);
+    }
+
+    #[rstest(
+        reversed,
+        view,
+        expected,
+        case(true, View::Sorted, " - reversed (C-r)"),
+        case(true, View::All, ""),
+        case(false, View::Sorted, "")
+    )]
+    fn format_reversed(reversed: bool, view: View, expected: &str, mut fake_state: State) {
+        fake_state.reversed = reversed;
+        fake_state.view = view;
+        assert_eq!(super::pp::reversed(&fake_state), expected);
+    }
+
+    #[rstest(value, expected, case(true, " - unique (C-b)"), case(false, ""))]
+    fn format_unique_filter(value: bool, expected: &str) {
+        assert_eq!(super::pp::unique_filter(value), expected);
     }
 
     #[rstest(
@@ -543,7 +2766,8 @@ mod tests {
         expected,
         case(SearchMode::Exact, "exact"),
         case(SearchMode::Regex, "regex"),
-        case(SearchMode::Fuzzy, "fuzzy")
+        case(SearchMode::Fuzzy, "fuzzy"),
+        case(SearchMode::Loose, "loose")
     )]
     fn format_search_mode(value: SearchMode, expected: &str) {
         assert_eq!(super::pp::search_mode(value), expected);